@@ -0,0 +1,204 @@
+//! Generate the tables embedded in the kernel.
+//!
+//! `src/link_app.S` is the table of user apps.
+//! Every `*.elf` file in `USER_BIN_DIR` becomes one app, in name order.
+//! The table has the layout `AppManager` expects:
+//! - `_num_app`: the number of apps, followed by the start address of each app
+//! and the end address of the last one.
+//! - `_app_time_limits`: the CPU time limit of each app in milliseconds, in the same order.
+//! It is read from the optional file `<app>.time_limit` next to `<app>.elf`;
+//! 0 means the kernel default.
+//! - `_app_names`: one NUL-terminated name per app, in the same order.
+//!
+//! `src/ksyms.S` is the kernel symbol table used by `ksym`.
+//! It is built from the file named by `KERNEL_SYMBOLS`, the output of
+//! `rust-nm -n -C --defined-only` on a previous link of the kernel (see `test.sh`).
+//! Without it the table is empty.
+
+use std::env;
+use std::fs::{read_dir, read_to_string, File};
+use std::io::{Result, Write};
+
+/// Directory holding the user app ELF images, relative to the kernel crate
+static USER_BIN_DIR: &str = "../user/bin/";
+
+fn main() {
+    println!("cargo:rerun-if-changed={}", USER_BIN_DIR);
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-env-changed=KERNEL_SYMBOLS");
+    insert_app_data().unwrap();
+    insert_kernel_symbols().unwrap();
+}
+
+/// Collect the app names, i.e., the file stems of the `*.elf` files.
+fn app_names() -> Vec<String> {
+    let mut apps: Vec<String> = match read_dir(USER_BIN_DIR) {
+        Ok(dir) => dir
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| entry.file_name().into_string().ok())
+            .filter_map(|name| name.strip_suffix(".elf").map(String::from))
+            .collect(),
+        Err(_) => {
+            println!("cargo:warning=no user apps found in {}", USER_BIN_DIR);
+            Vec::new()
+        }
+    };
+    apps.sort();
+    apps
+}
+
+/// The CPU time limit of `app` in milliseconds, or 0 if it has none.
+fn app_time_limit(app: &str) -> u64 {
+    let path = format!("{}{}.time_limit", USER_BIN_DIR, app);
+    match read_to_string(&path) {
+        Ok(limit) => limit.trim().parse().unwrap_or_else(|_| {
+            println!("cargo:warning=ignoring bad time limit in {}", path);
+            0
+        }),
+        Err(_) => 0,
+    }
+}
+
+fn insert_app_data() -> Result<()> {
+    let apps = app_names();
+    let mut f = File::create("src/link_app.S")?;
+    writeln!(
+        f,
+        r#"    .align 3
+    .section .data
+    .global _num_app
+_num_app:
+    .quad {}"#,
+        apps.len()
+    )?;
+    for i in 0..apps.len() {
+        writeln!(f, r#"    .quad app_{}_start"#, i)?;
+    }
+    match apps.len() {
+        0 => writeln!(f, r#"    .quad 0"#)?,
+        n => writeln!(f, r#"    .quad app_{}_end"#, n - 1)?,
+    }
+
+    writeln!(
+        f,
+        r#"
+    .global _app_time_limits
+_app_time_limits:"#
+    )?;
+    for app in apps.iter() {
+        writeln!(f, r#"    .quad {}"#, app_time_limit(app))?;
+    }
+
+    writeln!(
+        f,
+        r#"
+    .global _app_names
+_app_names:"#
+    )?;
+    for app in apps.iter() {
+        writeln!(f, r#"    .string "{}""#, asm_string(app))?;
+    }
+
+    for (idx, app) in apps.iter().enumerate() {
+        writeln!(
+            f,
+            r#"
+    .section .data
+    .align 3
+    .global app_{0}_start
+    .global app_{0}_end
+app_{0}_start:
+    .incbin "{1}"
+app_{0}_end:"#,
+            idx,
+            asm_string(&format!("{}{}.elf", USER_BIN_DIR, app))
+        )?;
+    }
+    Ok(())
+}
+
+/// `s` as the inside of a string literal in the generated assembly.
+///
+/// Escapes `\` and `"` for the assembler, and `{` and `}` because the files
+/// are included with `global_asm!`, which reads them as format strings.
+fn asm_string(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('{', "{{")
+        .replace('}', "}}")
+}
+
+/// Text symbols `(address, name)` from `rust-nm` output, sorted by address.
+///
+/// Rust symbol hashes (`::h0123456789abcdef`) are dropped to keep the table small.
+fn kernel_symbols(nm_output: &str) -> Vec<(u64, String)> {
+    let mut symbols: Vec<(u64, String)> = nm_output
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.splitn(3, ' ');
+            let addr = u64::from_str_radix(fields.next()?, 16).ok()?;
+            let kind = fields.next()?;
+            let name = fields.next()?;
+            if kind != "t" && kind != "T" {
+                return None;
+            }
+            let name = match name.rfind("::h") {
+                Some(pos)
+                    if name.len() - pos == 19
+                        && name[pos + 3..].chars().all(|c| c.is_ascii_hexdigit()) =>
+                {
+                    &name[..pos]
+                }
+                _ => name,
+            };
+            Some((addr, name.to_string()))
+        })
+        .collect();
+    symbols.sort_by_key(|&(addr, _)| addr);
+    symbols.dedup_by_key(|&mut (addr, _)| addr);
+    symbols
+}
+
+fn insert_kernel_symbols() -> Result<()> {
+    let symbols = match env::var("KERNEL_SYMBOLS") {
+        Ok(path) => {
+            println!("cargo:rerun-if-changed={}", path);
+            kernel_symbols(&read_to_string(path)?)
+        }
+        Err(_) => Vec::new(),
+    };
+    let mut f = File::create("src/ksyms.S")?;
+    writeln!(
+        f,
+        r#"    .section .rodata.ksyms
+    .align 3
+    .global _ksym_num
+_ksym_num:
+    .quad {}
+    .global _ksym_addrs
+_ksym_addrs:"#,
+        symbols.len()
+    )?;
+    for (addr, _) in symbols.iter() {
+        writeln!(f, r#"    .quad {:#x}"#, addr)?;
+    }
+    writeln!(
+        f,
+        r#"    .global _ksym_name_offsets
+_ksym_name_offsets:"#
+    )?;
+    let mut offset = 0;
+    for (_, name) in symbols.iter() {
+        writeln!(f, r#"    .quad {}"#, offset)?;
+        offset += name.len() + 1;
+    }
+    writeln!(
+        f,
+        r#"    .global _ksym_names
+_ksym_names:"#
+    )?;
+    for (_, name) in symbols.iter() {
+        writeln!(f, r#"    .string "{}""#, asm_string(name))?;
+    }
+    Ok(())
+}
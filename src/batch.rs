@@ -1,17 +1,19 @@
 //! batch subsystem
+//!
+//! Keeps the table of apps embedded in the kernel and loads each of them
+//! into its own memory slot, with its own kernel and user stack.
+//! Which app runs when is decided by the `task` subsystem.
 
-use crate::sync::UPSafeCell;
+use crate::config::*;
+use crate::elf::{ElfError, ElfFile, PT_LOAD};
+use crate::qemu::{self, ExitStatus};
+use crate::sync::SpinMutex;
 use crate::trap::TrapContext;
+use alloc::vec::Vec;
 use lazy_static::*;
 
-const USER_STACK_SIZE: usize = 4096 * 2;
-const KERNEL_STACK_SIZE: usize = 4096 * 2;
-const MAX_APP_NUM: usize = 16;
-const APP_BASE_ADDRESS: usize = 0x80400000;
-const APP_SIZE_LIMIT: usize = 0x20000;
-
-
 #[repr(align(4096))]
+#[derive(Copy, Clone)]
 /// The struct for kernel stack, which is just a fixed-size static byte array.
 /// 
 /// `#[repr(align(4096))]` forces the compiler to align the type to 4096=0x1000 bytes
@@ -21,6 +23,7 @@ struct KernelStack {
 }
 
 #[repr(align(4096))]
+#[derive(Copy, Clone)]
 /// The struct for user stack, which is just a fixed-size static byte array.
 /// 
 /// `#[repr(align(4096))]` forces the compiler to align th type to 4096=0x1000 bytes
@@ -29,31 +32,32 @@ struct UserStack {
     data: [u8; USER_STACK_SIZE],
 }
 
-/// The kernel stack. It is a global instance store in the bss segment.
-static KERNEL_STACK: KernelStack = KernelStack {
+/// The kernel stacks, one per app. They are global instances stored in the bss segment.
+///
+/// A kernel stack holds the `TrapContext` of its app, and the `TaskContext`
+/// frames while the app is switched out.
+static KERNEL_STACK: [KernelStack; MAX_APP_NUM] = [KernelStack {
     data: [0; KERNEL_STACK_SIZE],
-};
+}; MAX_APP_NUM];
 
-/// The user stack. It is a global instance store in the bss segment.
-/// 
-/// It stores the register state when context switch happens. 
-static USER_STACK: UserStack = UserStack {
+/// The user stacks, one per app. They are global instances stored in the bss segment.
+static USER_STACK: [UserStack; MAX_APP_NUM] = [UserStack {
     data: [0; USER_STACK_SIZE],
-};
+}; MAX_APP_NUM];
 
 impl KernelStack {
     /// Get the stack pointer. 
     /// 
-    /// For now, we only need to save one TrapContext at a time, so we always push context
-    /// at the bottom of the stack. This is actually the stack base.
+    /// The `TrapContext` of an app is always pushed at the bottom of its stack.
+    /// This is actually the stack base.
     /// As the stack is growing down toward lower addresses, it is the highest address.
     fn get_sp(&self) -> usize {
         self.data.as_ptr() as usize + KERNEL_STACK_SIZE
     }
     /// Push the context at the buttom of the stack. 
     /// 
-    /// Note that it is above the `sp`, so `__restore` first `mv sp, a0` to make it
-    /// point to the `TrapContext`, i.e., the stack top.
+    /// Note that it is above the `sp`: the `TaskContext` of a new task points `sp`
+    /// at the returned `TrapContext`, i.e., the stack top, before `__restore` runs.
     /// 
     /// The return type has lifetime `'static`,
     /// which is a special lifetime: the lifetime of the entire process. The funtion 
@@ -67,6 +71,14 @@ impl KernelStack {
     }
 }
 
+/// The kernel stack containing `addr`, as `[bottom, top)`, for the stack trace
+pub fn kernel_stack_containing(addr: usize) -> Option<(usize, usize)> {
+    KERNEL_STACK
+        .iter()
+        .map(|stack| (stack.data.as_ptr() as usize, stack.get_sp()))
+        .find(|&(bottom, top)| addr > bottom && addr <= top)
+}
+
 impl UserStack {
     /// Get the stack base. 
     /// 
@@ -77,110 +89,257 @@ impl UserStack {
     }
 }
 
+/// What to do with apps that break `MAX_APP_NUM` or `APP_SIZE_LIMIT`.
+///
+/// Selected at build time with the `APP_LIMIT_POLICY` environment variable.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum LimitPolicy {
+    /// Report the offending apps and run the others (`APP_LIMIT_POLICY=SKIP`, the default)
+    Skip,
+    /// Report the offending apps and refuse to boot (`APP_LIMIT_POLICY=REFUSE`)
+    Refuse,
+}
+
+fn limit_policy() -> LimitPolicy {
+    env_choice(
+        option_env!("APP_LIMIT_POLICY"),
+        &[("SKIP", LimitPolicy::Skip), ("REFUSE", LimitPolicy::Refuse)],
+        LimitPolicy::Skip,
+    )
+}
+
+/// The CPU time limit of apps without their own limit, in milliseconds.
+///
+/// Set at build time with the `APP_TIME_LIMIT_MS` environment variable.
+fn default_time_limit_ms() -> usize {
+    env_usize(option_env!("APP_TIME_LIMIT_MS"), DEFAULT_APP_TIME_LIMIT_MS)
+}
+
+/// Where an application image is stored in the kernel, its name,
+/// the memory slot it is loaded to and its CPU time limit
+#[derive(Clone, Copy)]
+struct AppInfo {
+    name: &'static str,
+    start: usize,
+    end: usize,
+    base: usize,
+    time_limit_ms: usize,
+}
+
+impl AppInfo {
+    /// The image bytes embedded in the kernel
+    fn image(&self) -> &'static [u8] {
+        unsafe { core::slice::from_raw_parts(self.start as *const u8, self.end - self.start) }
+    }
+
+    /// Bytes the app occupies once loaded.
+    ///
+    /// This is the span of its `PT_LOAD` segments. Images that cannot be parsed are
+    /// left to the loader to reject, so their file size is used instead.
+    fn load_size(&self) -> usize {
+        ElfFile::parse(self.image())
+            .ok()
+            .and_then(|elf| elf.memory_range())
+            .map_or(self.end - self.start, |(start, end)| end - start)
+    }
+}
+
 /// Struct for APP_MANAGER.
 /// 
 /// Have the info about
-/// - the total number of user application
-/// - the current app running
-/// - the starting address of each application and the end of the last one
+/// the location, the name, the memory slot and the CPU time limit of each application.
+///
+/// Only apps that passed the checks of `AppManager::from_link_app` are kept,
+/// so `apps` never exceeds `MAX_APP_NUM` or `APP_SIZE_LIMIT`.
+/// A skipped app leaves its slot empty, so the other apps keep the slot
+/// they were linked for.
 struct AppManager {
-    num_app: usize,
-    current_app: usize,
-    app_start: [usize; MAX_APP_NUM + 1]
+    apps: Vec<AppInfo>,
 }
 
 lazy_static! {
-    /// The global instance that keeps track of which app is currently running.
-    /// 
-    /// We want a global variable, but it also need to be safe, so we can't simply use `static mut`.
+    /// The global instance that keeps track of the apps in the batch.
     ///
-    /// ### `lazy_static!`
-    /// > It ensures the value is initialized exactly once when first used, 
-    /// and prevents reads from uninitialized memory.
-    /// 
-    /// Usaully, static variable need to be initialized at the compiling stage. However, 
-    /// `APP_MANAGER`'s initialization depends on the runtime value `_num_app()`.
-    /// By using the`lazy_static!` macro, the static variable will be initailized when it is first used.
-    /// It also ensures every read happens after it’s initialized. 
-    /// 
-    /// ### `UPSafeCell`
-    /// > It wraps the value with RefCell to enforce Rust’s borrowing rules at runtime, 
-    /// preventing multiple mutable borrows, even on a uniprocessor.
-    /// 
-    /// `static mut` is allowed to be borrowed multiple times, which may lead to unpredictable behavior,
-    /// as the Rust compiler does not enforce safety guarantees.
-    /// To address this issue, we use the `UPSafeCell` container, which triggers a panic
-    /// if multiple borrows occur.
-    static ref APP_MANAGER: UPSafeCell<AppManager> = unsafe {
-        UPSafeCell::new({
-            extern "C" {
-                fn _num_app();
-            }
-            let num_app_ptr = _num_app as usize as *const usize;
-            let num_app = num_app_ptr.read_volatile();
-            let mut app_start: [usize; MAX_APP_NUM + 1] = [0; MAX_APP_NUM + 1];
-            let app_start_raw: &[usize] =
-                core::slice::from_raw_parts(num_app_ptr.add(1), num_app + 1);
-            app_start[..=num_app].copy_from_slice(app_start_raw);
-            AppManager {
-                num_app,
-                current_app: 0,
-                app_start,
-            }
-        })
-    };
+    /// It is built on first use from the tables `build.rs` generates into `link_app.S`.
+    /// The `SpinMutex` hands out one guard at a time across harts. It does not
+    /// mask interrupts, so code running in a trap on the hart that holds it
+    /// must not take it again.
+    static ref APP_MANAGER: SpinMutex<AppManager> = SpinMutex::new(unsafe {
+        AppManager::from_link_app()
+    });
 }
 
 impl AppManager {
+    /// Build the app table from the symbols in `link_app.S`, checking the limits.
+    ///
+    /// Every app whose index is beyond `MAX_APP_NUM` or whose loaded size is beyond
+    /// `APP_SIZE_LIMIT` is reported with its name, link index, size and the limit.
+    /// It is named, not numbered: `app_<i>` in later messages is the index
+    /// among the apps kept, which differs from the link index after a rejection.
+    /// Depending on `limit_policy()`, the offending apps are then left out,
+    /// or the kernel refuses to boot once all of them have been reported.
+    ///
+    /// The per-app kernel and user stacks are fixed-size arrays, so an app
+    /// is only added to the table once its index is checked.
+    unsafe fn from_link_app() -> Self {
+        extern "C" {
+            fn _num_app();
+            fn _app_time_limits();
+            fn _app_names();
+        }
+        let num_app_ptr = _num_app as usize as *const usize;
+        let num_app_raw = unsafe { num_app_ptr.read_volatile() };
+        let app_start_raw: &[usize] =
+            unsafe { core::slice::from_raw_parts(num_app_ptr.add(1), num_app_raw + 1) };
+        let time_limits_raw: &[usize] = unsafe {
+            core::slice::from_raw_parts(_app_time_limits as usize as *const usize, num_app_raw)
+        };
+        let mut manager = Self {
+            apps: Vec::with_capacity(num_app_raw.min(MAX_APP_NUM)),
+        };
+        let mut rejected = 0;
+        // the names are NUL-terminated strings stored back to back
+        let mut name_ptr = _app_names as usize as *const u8;
+        for i in 0..num_app_raw {
+            let name = unsafe {
+                let mut len = 0;
+                while name_ptr.add(len).read_volatile() != 0 {
+                    len += 1;
+                }
+                let bytes = core::slice::from_raw_parts(name_ptr, len);
+                name_ptr = name_ptr.add(len + 1);
+                core::str::from_utf8(bytes).unwrap_or("<invalid name>")
+            };
+            let app = AppInfo {
+                name,
+                start: app_start_raw[i],
+                end: app_start_raw[i + 1],
+                base: APP_BASE_ADDRESS + i * APP_SIZE_LIMIT,
+                time_limit_ms: match time_limits_raw[i] {
+                    0 => default_time_limit_ms(),
+                    ms => ms,
+                },
+            };
+            let size = app.load_size();
+            if i >= MAX_APP_NUM {
+                println!(
+                    "[kernel] app {} rejected: link index {} exceeds MAX_APP_NUM = {}",
+                    name, i, MAX_APP_NUM
+                );
+                rejected += 1;
+            } else if size > APP_SIZE_LIMIT {
+                println!(
+                    "[kernel] app {} rejected: link index {}, size {:#x} exceeds APP_SIZE_LIMIT = {:#x}",
+                    name, i, size, APP_SIZE_LIMIT
+                );
+                rejected += 1;
+            } else {
+                manager.apps.push(app);
+            }
+        }
+        if rejected > 0 {
+            match limit_policy() {
+                LimitPolicy::Skip => {
+                    println!(
+                        "[kernel] {} app(s) rejected, running the other {}",
+                        rejected,
+                        manager.apps.len()
+                    );
+                }
+                LimitPolicy::Refuse => {
+                    println!("[kernel] {} app(s) rejected, refusing to boot", rejected);
+                    qemu::exit(ExitStatus::AppFailed);
+                }
+            }
+        }
+        manager
+    }
+
     /// Print the location where the application binary is stored in memory.
     pub fn print_app_info(&self) {
-        println!("[kernel] num_app = {}", self.num_app);
-        for i in 0..self.num_app {
+        println!("[kernel] num_app = {}", self.apps.len());
+        for (i, app) in self.apps.iter().enumerate() {
             println!(
-                "[kernel] app_{} [{:#x}, {:#x})",
+                "[kernel] app_{} {} [{:#x}, {:#x}) -> slot {:#x}, time limit {} ms",
                 i,
-                self.app_start[i],
-                self.app_start[i + 1]
+                app.name,
+                app.start,
+                app.end,
+                app.base,
+                app.time_limit_ms
             );
         }
     }
 
-    /// Copy the application binary to `0x80400000`
-    /// 
-    /// 1. Clear the memory, including instruction cache. Because we're changing the memory content where the CPU
-    /// is pointing at, there will be inconsistency between memory and cache.
-    /// 2. Find the location (address in memory) of the target application binary. 
-    /// This information comes from external symbol in `link_app.S` and
+    /// Load the application ELF image into its memory slot
+    /// `[base, base + APP_SIZE_LIMIT)` and return its entry point.
+    ///
+    /// 1. Find the location (address in memory) of the target application image.
+    /// This information comes from external symbol in the generated `link_app.S` and
     /// is store in the global instance `APP_MANAGER`.
-    /// 3. Do the copy.
-    unsafe fn load_app(&self, app_id: usize) {
-        if app_id >= self.num_app {
-            println!("All application completed!");
-            crate::sbi::shutdown();
+    /// 2. Parse the ELF header and check that every `PT_LOAD` segment fits in the slot.
+    /// Nothing is written to memory if the image is rejected.
+    /// 3. Clear the slot, then copy each segment to its virtual address.
+    /// The part of a segment past its file size (BSS) is left zeroed.
+    /// 4. Clear the instruction cache. Because we're changing the memory content where the CPU
+    /// is pointing at, there will be inconsistency between memory and cache.
+    unsafe fn load_app(&self, app_id: usize) -> Result<usize, ElfError> {
+        let base = self.apps[app_id].base;
+        let elf = ElfFile::parse(self.apps[app_id].image())?;
+        let mut loadable = false;
+        let mut entry_found = false;
+        for i in 0..elf.program_header_count() {
+            let ph = elf.program_header(i);
+            if ph.p_type != PT_LOAD {
+                continue;
+            }
+            if ph.filesz > ph.memsz || elf.segment_data(&ph).is_none() {
+                return Err(ElfError::BadSegment(i));
+            }
+            let end = ph.vaddr.checked_add(ph.memsz);
+            match end {
+                Some(end) if ph.vaddr >= base && end <= base + APP_SIZE_LIMIT => {
+                    if (ph.vaddr..end).contains(&elf.entry()) {
+                        entry_found = true;
+                    }
+                }
+                _ => {
+                    return Err(ElfError::SegmentOutOfRange {
+                        index: i,
+                        start: ph.vaddr,
+                        end: ph.vaddr.wrapping_add(ph.memsz),
+                    })
+                }
+            }
+            loadable = true;
         }
-        println!("[kernel] Loading app_{}", app_id);
-        // clear icache
-        unsafe { core::arch::asm!("fence.i"); }
-        // clear app area
+        if !loadable {
+            return Err(ElfError::NoLoadableSegment);
+        }
+        if !entry_found {
+            return Err(ElfError::BadEntry(elf.entry()));
+        }
+        // clear the slot, which also zeroes the BSS part of every segment
         unsafe {
-            core::slice::from_raw_parts_mut(APP_BASE_ADDRESS as *mut u8, APP_SIZE_LIMIT).fill(0);
-            let app_src = core::slice::from_raw_parts(
-                self.app_start[app_id] as *const u8,
-                self.app_start[app_id + 1] - self.app_start[app_id],
-            );
-            let app_dst = core::slice::from_raw_parts_mut(APP_BASE_ADDRESS as *mut u8, app_src.len());
-            app_dst.copy_from_slice(app_src);
+            core::slice::from_raw_parts_mut(base as *mut u8, APP_SIZE_LIMIT).fill(0);
         }
+        for i in 0..elf.program_header_count() {
+            let ph = elf.program_header(i);
+            if ph.p_type != PT_LOAD {
+                continue;
+            }
+            let seg_src = elf.segment_data(&ph).unwrap();
+            let seg_dst = unsafe { core::slice::from_raw_parts_mut(ph.vaddr as *mut u8, ph.filesz) };
+            seg_dst.copy_from_slice(seg_src);
+        }
+        // clear icache
+        unsafe { core::arch::asm!("fence.i"); }
+        Ok(elf.entry())
     }
 
-    /// Get the current app index
-    pub fn get_current_app(&self) -> usize {
-        self.current_app
-    }
-
-    /// Increment the current app index
-    pub fn move_to_next_app(&mut self) {
-        self.current_app += 1;
+    /// Get the name of the app with index `app_id`
+    pub fn get_app_name(&self, app_id: usize) -> &'static str {
+        self.apps[app_id].name
     }
 }
 
@@ -191,34 +350,65 @@ pub fn init() {
 
 /// print apps info
 pub fn print_app_info() {
-    APP_MANAGER.exclusive_access().print_app_info();
+    APP_MANAGER.lock().print_app_info();
 }
 
-/// run next app
-/// 
-/// To use `APP_MANAGER`, we get a mutable reference of it. 
-/// And we have to drop it manually
-/// 
-/// Set the physical context for the user program as if we
-/// are returning to it from a trap.
-pub fn run_next_app() -> ! {
-    let mut app_manager = APP_MANAGER.exclusive_access();
-    let current_app = app_manager.get_current_app();
-    unsafe {
-        app_manager.load_app(current_app);
-    }
-    app_manager.move_to_next_app();
-    drop(app_manager);
-    // before this we have to drop local variables related to resources manually
-    // and release the resources
-    extern "C" {
-        fn __restore(cx_addr: usize);
-    }
-    unsafe {
-        __restore(KERNEL_STACK.push_context(TrapContext::app_init_context(
-            APP_BASE_ADDRESS,
-            USER_STACK.get_sp(),
-        )) as *const _ as usize);
+/// Get the number of apps
+pub fn get_num_app() -> usize {
+    APP_MANAGER.lock().apps.len()
+}
+
+/// Get the name of app `app_id`
+pub fn get_app_name(app_id: usize) -> &'static str {
+    APP_MANAGER.lock().get_app_name(app_id)
+}
+
+/// Get the CPU time limit of app `app_id` in milliseconds
+pub fn get_app_time_limit_ms(app_id: usize) -> usize {
+    APP_MANAGER.lock().apps[app_id].time_limit_ms
+}
+
+/// The memory app `app_id` may use, as `[start, end)` ranges:
+/// its slot and its user stack
+pub fn app_user_ranges(app_id: usize) -> [(usize, usize); 2] {
+    let base = APP_MANAGER.lock().apps[app_id].base;
+    let stack_top = USER_STACK[app_id].get_sp();
+    [
+        (base, base + APP_SIZE_LIMIT),
+        (stack_top - USER_STACK_SIZE, stack_top),
+    ]
+}
+
+/// Load app `app_id` into its slot and return its entry point.
+///
+/// The error is reported here; the caller only decides what to do with the app.
+pub fn load_app(app_id: usize) -> Result<usize, ElfError> {
+    let app_manager = APP_MANAGER.lock();
+    println!(
+        "[kernel] Loading app_{} ({})",
+        app_id,
+        app_manager.get_app_name(app_id)
+    );
+    let loaded = unsafe { app_manager.load_app(app_id) };
+    if let Err(err) = loaded {
+        println!(
+            "[kernel] Failed to load app_{} ({}): {}",
+            app_id,
+            app_manager.get_app_name(app_id),
+            err
+        );
     }
-    panic!("Unreachable in batch::run_current_app!");
-}
\ No newline at end of file
+    loaded
+}
+
+/// Set up the initial `TrapContext` of app `app_id` on its kernel stack
+/// and return its address.
+///
+/// The context makes the app start at `entry`, in user mode, with an empty user stack,
+/// as if we are returning to it from a trap.
+pub fn init_app_cx(app_id: usize, entry: usize) -> usize {
+    KERNEL_STACK[app_id].push_context(TrapContext::app_init_context(
+        entry,
+        USER_STACK[app_id].get_sp(),
+    )) as *const _ as usize
+}
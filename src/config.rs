@@ -0,0 +1,60 @@
+//! Constants used in the kernel, and the parsing of options set at build time
+
+/// Size of the user stack of each app
+pub const USER_STACK_SIZE: usize = 4096 * 2;
+/// Size of the kernel stack of each app
+pub const KERNEL_STACK_SIZE: usize = 4096 * 2;
+/// Size of the boot stack of each hart, passed to `entry.asm`
+pub const BOOT_STACK_SIZE: usize = 4096 * 16;
+/// Maximum number of harts; harts with a larger id are not used.
+/// Passed to `entry.asm`, which reserves a boot stack for each of them.
+pub const MAX_HARTS: usize = 8;
+/// Maximum number of apps in a batch
+pub const MAX_APP_NUM: usize = 16;
+/// Start of the memory slot of the first app.
+///
+/// App `i` (in the order of the app table) owns the slot
+/// `[APP_BASE_ADDRESS + i * APP_SIZE_LIMIT, APP_BASE_ADDRESS + (i + 1) * APP_SIZE_LIMIT)`,
+/// and every `PT_LOAD` segment of its image must lie inside it.
+pub const APP_BASE_ADDRESS: usize = 0x80400000;
+/// Size of the memory slot of each app
+pub const APP_SIZE_LIMIT: usize = 0x20000;
+/// Start of the kernel heap, right after the app slots.
+///
+/// The heap is kept out of the kernel image, which has to end below
+/// `APP_BASE_ADDRESS` with every app image embedded in it, see `linker.ld`.
+pub const KERNEL_HEAP_BASE: usize = APP_BASE_ADDRESS + MAX_APP_NUM * APP_SIZE_LIMIT;
+/// Size of the kernel heap
+pub const KERNEL_HEAP_SIZE: usize = 0x80000;
+/// Frequency of the `time` CSR used when the device tree does not give
+/// `timebase-frequency`. This is the timebase frequency of QEMU virt.
+pub const DEFAULT_CLOCK_FREQ: usize = 12500000;
+/// Time slice of a task in milliseconds, unless `TIME_SLICE_MS` is set at build time
+pub const DEFAULT_TIME_SLICE_MS: usize = 10;
+/// CPU time limit of an app in milliseconds, unless `APP_TIME_LIMIT_MS` is set at build time
+/// or the app has its own limit in the app table
+pub const DEFAULT_APP_TIME_LIMIT_MS: usize = 10_000;
+/// How often `sys_read` on stdin checks the console while the app waits for input
+pub const STDIN_POLL_INTERVAL_MS: usize = 10;
+
+/// A positive number set at build time, or `default`.
+///
+/// `value` is `option_env!` of the variable; a value that is not
+/// a positive number is ignored.
+pub fn env_usize(value: Option<&str>, default: usize) -> usize {
+    value
+        .and_then(|value| value.parse().ok())
+        .filter(|&value| value > 0)
+        .unwrap_or(default)
+}
+
+/// The choice named by an option set at build time, or `default`.
+///
+/// `value` is `option_env!` of the variable, and `choices` pairs each
+/// accepted value, e.g. `"KILL"`, with what it selects. Anything else is ignored.
+pub fn env_choice<T: Copy>(value: Option<&str>, choices: &[(&str, T)], default: T) -> T {
+    choices
+        .iter()
+        .find(|&&(name, _)| Some(name) == value)
+        .map_or(default, |&(_, choice)| choice)
+}
@@ -10,13 +10,29 @@ struct Stdout;
 /// This is inside the OS kernel, so we directly write through sbi call.
 impl Write for Stdout {
     fn write_str(&mut self, s: &str) -> core::fmt::Result {
-        for c in s.chars() {
-            crate::sbi::console_putchar(c as usize);
-        }
+        write_bytes(s.as_bytes());
         Ok(())
     }
 }
 
+/// Write raw bytes to the console.
+///
+/// The bytes are not required to be UTF-8: a multi-byte character is sent
+/// as its encoded bytes, and the terminal decodes it.
+/// While GDB waits for the app it resumed, the bytes are sent to GDB instead.
+pub fn write_bytes(bytes: &[u8]) {
+    if !crate::gdb::console_output(bytes) {
+        crate::sbi::console_write(bytes);
+    }
+}
+
+/// Read a byte from the console, if one is available.
+///
+/// Does not wait: `None` means nothing has been typed yet.
+pub fn read_byte() -> Option<u8> {
+    crate::sbi::console_getchar()
+}
+
 /// Print interface.
 ///
 /// Call `Stdout`'s `Write` interface. Used by the marco.
@@ -0,0 +1,201 @@
+//! Minimal ELF64 parser for user applications
+//!
+//! Only the parts the loader needs are implemented: the file header and the
+//! program header table of a little-endian RISC-V executable.
+//! All fields are read byte by byte, so the image does not need to be aligned.
+
+use core::fmt;
+
+const ELF_MAGIC: [u8; 4] = [0x7f, b'E', b'L', b'F'];
+const ELFCLASS64: u8 = 2;
+const ELFDATA2LSB: u8 = 1;
+const ET_EXEC: u16 = 2;
+const EM_RISCV: u16 = 243;
+
+/// Size of the ELF64 file header
+const EHDR_SIZE: usize = 64;
+/// Size of an ELF64 program header
+const PHDR_SIZE: usize = 56;
+
+/// Program header type of a loadable segment
+pub const PT_LOAD: u32 = 1;
+
+/// Reasons for rejecting an ELF image
+#[derive(Debug, Clone, Copy)]
+pub enum ElfError {
+    /// The image is smaller than the ELF header
+    TooShort,
+    /// The image does not start with `\x7fELF`
+    BadMagic,
+    /// Not a 64-bit little-endian image
+    UnsupportedClass,
+    /// Not an executable (`ET_EXEC`)
+    NotExecutable,
+    /// Not built for RISC-V
+    WrongMachine(u16),
+    /// The program header table lies outside the image or has a bad entry size
+    BadProgramHeaders,
+    /// The file content of segment `index` lies outside the image,
+    /// or it is larger than its memory size
+    BadSegment(usize),
+    /// Segment `index` would be loaded to `[start, end)`, outside the allowed window
+    SegmentOutOfRange {
+        /// index of the program header
+        index: usize,
+        /// first byte of the segment in memory
+        start: usize,
+        /// end of the segment in memory
+        end: usize,
+    },
+    /// The image has no loadable segment
+    NoLoadableSegment,
+    /// The entry point is not inside any loadable segment
+    BadEntry(usize),
+}
+
+impl fmt::Display for ElfError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            ElfError::TooShort => write!(f, "image too short"),
+            ElfError::BadMagic => write!(f, "bad ELF magic"),
+            ElfError::UnsupportedClass => write!(f, "not a 64-bit little-endian ELF"),
+            ElfError::NotExecutable => write!(f, "not an executable"),
+            ElfError::WrongMachine(m) => write!(f, "wrong machine type {}", m),
+            ElfError::BadProgramHeaders => write!(f, "bad program header table"),
+            ElfError::BadSegment(i) => write!(f, "segment {} is malformed", i),
+            ElfError::SegmentOutOfRange { index, start, end } => write!(
+                f,
+                "segment {} [{:#x}, {:#x}) is out of range",
+                index, start, end
+            ),
+            ElfError::NoLoadableSegment => write!(f, "no loadable segment"),
+            ElfError::BadEntry(entry) => write!(f, "entry {:#x} is not in a loadable segment", entry),
+        }
+    }
+}
+
+/// A program header, with the fields we care about
+#[derive(Debug, Clone, Copy)]
+pub struct ProgramHeader {
+    /// segment type, e.g. `PT_LOAD`
+    pub p_type: u32,
+    /// offset of the segment content in the image
+    pub offset: usize,
+    /// virtual address the segment is loaded to
+    pub vaddr: usize,
+    /// bytes of the segment stored in the image
+    pub filesz: usize,
+    /// bytes of the segment in memory; the part past `filesz` is BSS
+    pub memsz: usize,
+}
+
+/// A validated ELF64 image borrowed from memory
+pub struct ElfFile<'a> {
+    data: &'a [u8],
+    entry: usize,
+    phoff: usize,
+    phnum: usize,
+}
+
+fn read_u16(data: &[u8], off: usize) -> u16 {
+    u16::from_le_bytes([data[off], data[off + 1]])
+}
+
+fn read_u32(data: &[u8], off: usize) -> u32 {
+    let mut bytes = [0u8; 4];
+    bytes.copy_from_slice(&data[off..off + 4]);
+    u32::from_le_bytes(bytes)
+}
+
+fn read_u64(data: &[u8], off: usize) -> u64 {
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&data[off..off + 8]);
+    u64::from_le_bytes(bytes)
+}
+
+impl<'a> ElfFile<'a> {
+    /// Check the file header and the bounds of the program header table.
+    ///
+    /// Segments themselves are checked by the caller, which knows where they may go.
+    pub fn parse(data: &'a [u8]) -> Result<Self, ElfError> {
+        if data.len() < EHDR_SIZE {
+            return Err(ElfError::TooShort);
+        }
+        if data[..4] != ELF_MAGIC {
+            return Err(ElfError::BadMagic);
+        }
+        if data[4] != ELFCLASS64 || data[5] != ELFDATA2LSB {
+            return Err(ElfError::UnsupportedClass);
+        }
+        if read_u16(data, 16) != ET_EXEC {
+            return Err(ElfError::NotExecutable);
+        }
+        let machine = read_u16(data, 18);
+        if machine != EM_RISCV {
+            return Err(ElfError::WrongMachine(machine));
+        }
+        let entry = read_u64(data, 24) as usize;
+        let phoff = read_u64(data, 32) as usize;
+        let phentsize = read_u16(data, 54) as usize;
+        let phnum = read_u16(data, 56) as usize;
+        let table_end = phnum
+            .checked_mul(PHDR_SIZE)
+            .and_then(|size| size.checked_add(phoff));
+        match table_end {
+            Some(end) if phentsize == PHDR_SIZE && end <= data.len() => {}
+            _ => return Err(ElfError::BadProgramHeaders),
+        }
+        Ok(Self {
+            data,
+            entry,
+            phoff,
+            phnum,
+        })
+    }
+
+    /// The entry point `e_entry`
+    pub fn entry(&self) -> usize {
+        self.entry
+    }
+
+    /// The program header at `index`
+    pub fn program_header(&self, index: usize) -> ProgramHeader {
+        let off = self.phoff + index * PHDR_SIZE;
+        ProgramHeader {
+            p_type: read_u32(self.data, off),
+            offset: read_u64(self.data, off + 8) as usize,
+            vaddr: read_u64(self.data, off + 16) as usize,
+            filesz: read_u64(self.data, off + 32) as usize,
+            memsz: read_u64(self.data, off + 40) as usize,
+        }
+    }
+
+    /// Number of program headers
+    pub fn program_header_count(&self) -> usize {
+        self.phnum
+    }
+
+    /// The memory range `[start, end)` covered by all `PT_LOAD` segments,
+    /// or `None` if there is no loadable segment or a segment wraps around.
+    pub fn memory_range(&self) -> Option<(usize, usize)> {
+        let mut range: Option<(usize, usize)> = None;
+        for i in 0..self.phnum {
+            let ph = self.program_header(i);
+            if ph.p_type != PT_LOAD {
+                continue;
+            }
+            let end = ph.vaddr.checked_add(ph.memsz)?;
+            range = Some(match range {
+                None => (ph.vaddr, end),
+                Some((start, last)) => (start.min(ph.vaddr), last.max(end)),
+            });
+        }
+        range
+    }
+
+    /// The file content of a segment, or `None` if it lies outside the image.
+    pub fn segment_data(&self, ph: &ProgramHeader) -> Option<&'a [u8]> {
+        let end = ph.offset.checked_add(ph.filesz)?;
+        self.data.get(ph.offset..end)
+    }
+}
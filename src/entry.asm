@@ -1,12 +1,38 @@
     .section .text.entry
     .globl _start
+    .globl _start_secondary
+# The firmware starts the boot hart at `_start`, and the other harts at
+# `_start_secondary` when we ask it to with SBI HSM, both with the hart id
+# in a0. Each hart keeps its id in `tp` and gets its own boot stack.
 _start:
+    la t1, rust_main
+    li t0, {max_harts}
+    bltu a0, t0, 1f
+    # the boot hart is past MAX_HARTS: borrow the boot stack of hart 0,
+    # which is never started, to report it and shut down
+    mv tp, a0
     la sp, boot_stack_top
-    call rust_main
+    call rust_main_unsupported_hart
+_start_secondary:
+    la t1, rust_main_secondary
+    # harts past MAX_HARTS are not used, nor started by `smp`
+    li t0, {max_harts}
+    bgeu a0, t0, 2f
+1:
+    mv tp, a0
+    # sp = boot_stack_top - hart_id * BOOT_STACK_SIZE
+    la sp, boot_stack_top
+    li t0, {boot_stack_size}
+    mul t0, t0, a0
+    sub sp, sp, t0
+    jr t1
+2:
+    wfi
+    j 2b
 
     .section .bss.stack
     .globl boot_stack
 boot_stack:
-    .space 4096 * 16
+    .space {boot_stack_size} * {max_harts}
     .globl boot_stack_top
 boot_stack_top:
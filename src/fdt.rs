@@ -0,0 +1,140 @@
+//! Minimal flattened device tree (FDT) reader
+//!
+//! The firmware passes the address of the device tree blob in `a1` when it jumps
+//! to the kernel. We only need a few properties from it, so instead of building
+//! a tree we walk the structure block once, tracking the path of the current node.
+//! All values in the blob are big-endian.
+
+const FDT_MAGIC: u32 = 0xd00dfeed;
+const FDT_BEGIN_NODE: u32 = 1;
+const FDT_END_NODE: u32 = 2;
+const FDT_PROP: u32 = 3;
+const FDT_NOP: u32 = 4;
+const FDT_END: u32 = 9;
+
+/// Nodes deeper than this are skipped
+const MAX_DEPTH: usize = 8;
+
+/// A device tree blob in memory
+pub struct Fdt {
+    base: usize,
+    size: usize,
+    off_struct: usize,
+    off_strings: usize,
+}
+
+fn align4(n: usize) -> usize {
+    (n + 3) & !3
+}
+
+impl Fdt {
+    /// Check the header of the blob at `addr`
+    ///
+    /// # Safety
+    ///
+    /// `addr` must be 0 or point to readable memory holding at least the header.
+    pub unsafe fn from_addr(addr: usize) -> Option<Self> {
+        if addr == 0 || addr % 4 != 0 {
+            return None;
+        }
+        let read = |off: usize| u32::from_be(unsafe { ((addr + off) as *const u32).read_volatile() });
+        if read(0) != FDT_MAGIC {
+            return None;
+        }
+        let fdt = Self {
+            base: addr,
+            size: read(4) as usize,
+            off_struct: read(8) as usize,
+            off_strings: read(12) as usize,
+        };
+        if fdt.off_struct >= fdt.size || fdt.off_strings >= fdt.size {
+            return None;
+        }
+        Some(fdt)
+    }
+
+    fn read_u32(&self, off: usize) -> Option<u32> {
+        if off + 4 > self.size {
+            return None;
+        }
+        Some(u32::from_be(unsafe { ((self.base + off) as *const u32).read_volatile() }))
+    }
+
+    /// The NUL-terminated string at `off`
+    fn read_str(&self, off: usize) -> Option<&'static [u8]> {
+        let mut len = 0;
+        loop {
+            if off + len >= self.size {
+                return None;
+            }
+            if unsafe { ((self.base + off + len) as *const u8).read_volatile() } == 0 {
+                break;
+            }
+            len += 1;
+        }
+        Some(unsafe { core::slice::from_raw_parts((self.base + off) as *const u8, len) })
+    }
+
+    /// Find the first property called `prop` in a node for which `matches`
+    /// returns true, given the names of the node and its ancestors
+    /// (without the root), and return its value.
+    pub fn find_property(
+        &self,
+        matches: impl Fn(&[&[u8]]) -> bool,
+        prop: &str,
+    ) -> Option<&'static [u8]> {
+        let mut path: [&[u8]; MAX_DEPTH] = [b"" as &[u8]; MAX_DEPTH];
+        // depth of the current node; the root is at depth 1
+        let mut depth = 0;
+        let mut off = self.off_struct;
+        loop {
+            let token = self.read_u32(off)?;
+            off += 4;
+            match token {
+                FDT_BEGIN_NODE => {
+                    let name = self.read_str(off)?;
+                    off = align4(off + name.len() + 1);
+                    if (1..=MAX_DEPTH).contains(&depth) {
+                        path[depth - 1] = name;
+                    }
+                    depth += 1;
+                }
+                FDT_END_NODE => depth = depth.checked_sub(1)?,
+                FDT_PROP => {
+                    let len = self.read_u32(off)? as usize;
+                    let name_off = self.read_u32(off + 4)? as usize;
+                    let value = off + 8;
+                    off = align4(value + len);
+                    if off > self.size {
+                        return None;
+                    }
+                    if depth == 0 || depth > MAX_DEPTH + 1 {
+                        continue;
+                    }
+                    let name = self.read_str(self.off_strings + name_off)?;
+                    if name == prop.as_bytes() && matches(&path[..depth - 1]) {
+                        return Some(unsafe {
+                            core::slice::from_raw_parts((self.base + value) as *const u8, len)
+                        });
+                    }
+                }
+                FDT_NOP => {}
+                FDT_END => return None,
+                _ => return None,
+            }
+        }
+    }
+}
+
+/// A property value holding a single 32-bit or 64-bit cell
+pub fn prop_to_usize(value: &[u8]) -> Option<usize> {
+    match value.len() {
+        4 => Some(u32::from_be_bytes([value[0], value[1], value[2], value[3]]) as usize),
+        8 => {
+            let mut bytes = [0u8; 8];
+            bytes.copy_from_slice(value);
+            Some(u64::from_be_bytes(bytes) as usize)
+        }
+        _ => None,
+    }
+}
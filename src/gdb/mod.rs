@@ -0,0 +1,416 @@
+//! GDB remote stub over the SBI console
+//!
+//! Build with `GDB_STUB=ON` to debug apps with `gdb` over the serial port,
+//! without QEMU's own gdbstub. The stub speaks the GDB Remote Serial Protocol
+//! and is entered when an app executes `ebreak`, or when the kernel panics.
+//!
+//! Supported commands: `?`, `g`/`G` and `p`/`P` on the registers of the
+//! `TrapContext`, `m`/`M` on memory, `c` and `s` to resume the app, `k` to
+//! kill it and `D` to detach. Anything else gets the empty reply, which GDB
+//! reads as "not supported". Breakpoints are written by GDB itself with `M`.
+//!
+//! An app may only be read and written inside its own memory, see `mm`;
+//! the kernel image can be read too. After a panic nothing can be written,
+//! and the stub only serves as a post-mortem view.
+//!
+//! While GDB waits for a stop reply, console output is sent to it in `O`
+//! packets, and the end of the app it resumed is reported with `W` or `X`.
+
+mod packet;
+mod step;
+
+use crate::batch::get_app_name;
+use crate::config::{env_choice, APP_BASE_ADDRESS, APP_SIZE_LIMIT, MAX_APP_NUM};
+use crate::mm::{user_slice, user_slice_mut};
+use crate::report::Outcome;
+use crate::task::{current_task_id, exclude_running_cpu_time, exit_current_and_run_next};
+use crate::timer::get_time;
+use crate::trap::TrapContext;
+use core::arch::asm;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use packet::{parse_hex, parse_hex_le, read_packet, Reply, PACKET_SIZE};
+use riscv::register::{scause::Exception, sstatus};
+
+/// Signal reported for an `ebreak`
+const SIGTRAP: u8 = 5;
+/// Signal reported for a kernel panic
+const SIGABRT: u8 = 6;
+/// Signals reported when an app is killed, in GDB's numbering
+const SIGILL: u8 = 4;
+const SIGKILL: u8 = 9;
+const SIGSEGV: u8 = 11;
+const SIGXCPU: u8 = 24;
+
+/// Number of the `pc` register in the GDB numbering, after `x0`-`x31`
+const REG_PC: usize = 32;
+
+/// Set while GDB waits for the stop reply of a `c` or `s`
+static RESUMED: AtomicBool = AtomicBool::new(false);
+/// Id of the app GDB last stopped in
+static DEBUGGED: AtomicUsize = AtomicUsize::new(0);
+
+/// Whether the stub is built in. Set at build time with `GDB_STUB=ON`.
+pub fn enabled() -> bool {
+    env_choice(option_env!("GDB_STUB"), &[("ON", true), ("OFF", false)], false)
+}
+
+/// What is being debugged
+#[derive(Clone, Copy)]
+enum Target {
+    /// The current app, stopped on a breakpoint
+    App,
+    /// The kernel, after a panic
+    Kernel,
+}
+
+/// How a session ends
+enum Resume {
+    Continue,
+    Step,
+    Kill,
+}
+
+/// Value of register `x<i>`; `x0` is not saved by `__alltraps`.
+fn reg(cx: &TrapContext, i: usize) -> usize {
+    if i == 0 {
+        0
+    } else {
+        cx.x[i]
+    }
+}
+
+/// Whether `[addr, addr + len)` lies inside the kernel image
+fn in_kernel_image(addr: usize, len: usize) -> bool {
+    extern "C" {
+        fn skernel();
+        fn ekernel();
+    }
+    addr >= skernel as usize
+        && addr
+            .checked_add(len)
+            .map_or(false, |end| end <= ekernel as usize)
+}
+
+/// Whether `[addr, addr + len)` lies inside the app slots
+fn in_app_slots(addr: usize, len: usize) -> bool {
+    addr >= APP_BASE_ADDRESS
+        && addr
+            .checked_add(len)
+            .map_or(false, |end| end <= APP_BASE_ADDRESS + MAX_APP_NUM * APP_SIZE_LIMIT)
+}
+
+/// Memory GDB may read
+fn readable(target: Target, addr: usize, len: usize) -> Option<&'static [u8]> {
+    if in_kernel_image(addr, len) {
+        return Some(unsafe { core::slice::from_raw_parts(addr as *const u8, len) });
+    }
+    match target {
+        Target::App => user_slice(addr as *const u8, len),
+        // the task manager may be borrowed, so do not ask it what the app owns
+        Target::Kernel => in_app_slots(addr, len)
+            .then(|| unsafe { core::slice::from_raw_parts(addr as *const u8, len) }),
+    }
+}
+
+/// Memory GDB may write
+fn writable(target: Target, addr: usize, len: usize) -> Option<&'static mut [u8]> {
+    match target {
+        Target::App => user_slice_mut(addr as *mut u8, len),
+        Target::Kernel => None,
+    }
+}
+
+/// Enter the stub on an `ebreak` of the current app.
+///
+/// Returns when GDB resumes the app, with `cx` as GDB left it.
+/// If GDB kills the app, this does not return.
+pub fn handle_breakpoint(cx: &mut TrapContext) {
+    let task_id = current_task_id();
+    DEBUGGED.store(task_id, Ordering::Relaxed);
+    let stepped = step::remove(task_id, cx.sepc);
+    if !RESUMED.load(Ordering::Relaxed) {
+        println!(
+            "[kernel] gdb: app_{} ({}) stopped at {:#x}, waiting for GDB",
+            task_id,
+            get_app_name(task_id),
+            cx.sepc
+        );
+    }
+    let stopped_at = get_time();
+    let resume = session(cx, Target::App, SIGTRAP);
+    // time spent in the debugger does not count against the time limit,
+    // neither for this app nor for the apps the kernel lock held up meanwhile
+    exclude_running_cpu_time(get_time() - stopped_at);
+    // The app's own `ebreak` would trap again right away, so skip it.
+    // One that GDB placed has been removed by now, and after a step
+    // the instruction at `sepc` has not run yet.
+    if !stepped {
+        skip_ebreak(cx);
+    }
+    match resume {
+        Resume::Continue => {}
+        Resume::Step => step::insert(cx, task_id),
+        Resume::Kill => {
+            println!("[kernel] gdb: app_{} killed", task_id);
+            exit_current_and_run_next(Outcome::Killed {
+                cause: Exception::Breakpoint,
+                sepc: cx.sepc,
+                stval: 0,
+            });
+        }
+    }
+}
+
+/// Tell GDB that app `task_id` left the batch with `outcome`,
+/// if GDB resumed it and waits for its stop reply.
+///
+/// Called whenever an app exits or is killed; without it, GDB would wait forever.
+pub fn report_exit(task_id: usize, outcome: &Outcome) {
+    if !enabled()
+        || DEBUGGED.load(Ordering::Relaxed) != task_id
+        || !RESUMED.swap(false, Ordering::Relaxed)
+    {
+        return;
+    }
+    let mut reply = Reply::new();
+    match *outcome {
+        Outcome::Exited(code) => {
+            reply.push_str("W");
+            reply.push_hex_u8(code as u8);
+        }
+        Outcome::Killed { cause, .. } => {
+            reply.push_str("X");
+            reply.push_hex_u8(match cause {
+                Exception::IllegalInstruction => SIGILL,
+                Exception::Breakpoint => SIGTRAP,
+                _ => SIGSEGV,
+            });
+        }
+        Outcome::TimeLimitExceeded { .. } => {
+            reply.push_str("X");
+            reply.push_hex_u8(SIGXCPU);
+        }
+        Outcome::BadSyscall { .. } | Outcome::LoadFailed => {
+            reply.push_str("X");
+            reply.push_hex_u8(SIGKILL);
+        }
+    }
+    reply.send();
+}
+
+/// Send console output to GDB in `O` packets if it waits for a stop reply,
+/// so the output does not get mixed with the packets.
+///
+/// Returns `false` if GDB does not wait, and the output goes to the console as usual.
+pub fn console_output(bytes: &[u8]) -> bool {
+    if !enabled() || !RESUMED.load(Ordering::Relaxed) {
+        return false;
+    }
+    // each byte takes two hex digits after the `O`
+    for chunk in bytes.chunks((PACKET_SIZE - 1) / 2) {
+        let mut reply = Reply::new();
+        reply.push_str("O");
+        for &byte in chunk {
+            reply.push_hex_u8(byte);
+        }
+        reply.send();
+    }
+    true
+}
+
+/// Move `sepc` past the `ebreak` or `c.ebreak` it points at, if any.
+fn skip_ebreak(cx: &mut TrapContext) {
+    const EBREAK: u32 = 0x0010_0073;
+    const C_EBREAK: u16 = 0x9002;
+    let inst = match user_slice(cx.sepc as *const u8, 2) {
+        Some(bytes) => u16::from_le_bytes([bytes[0], bytes[1]]),
+        None => return,
+    };
+    if inst == C_EBREAK {
+        cx.sepc += 2;
+    } else if let Some(bytes) = user_slice(cx.sepc as *const u8, 4) {
+        if u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) == EBREAK {
+            cx.sepc += 4;
+        }
+    }
+}
+
+/// Enter the stub after a kernel panic, for a post-mortem look at the kernel.
+///
+/// The registers shown are those of this function: GDB unwinds from there
+/// to the place of the panic.
+pub fn enter_on_panic() {
+    let (pc, ra, sp, gp, tp, fp): (usize, usize, usize, usize, usize, usize);
+    unsafe {
+        asm!(
+            "auipc {pc}, 0",
+            "mv {ra}, ra",
+            "mv {sp}, sp",
+            "mv {gp}, gp",
+            "mv {tp}, tp",
+            "mv {fp}, fp",
+            pc = out(reg) pc,
+            ra = out(reg) ra,
+            sp = out(reg) sp,
+            gp = out(reg) gp,
+            tp = out(reg) tp,
+            fp = out(reg) fp,
+        );
+    }
+    let mut cx = TrapContext {
+        x: [0; 32],
+        sstatus: sstatus::read(),
+        sepc: pc,
+        kernel_tp: tp,
+    };
+    cx.x[1] = ra;
+    cx.x[2] = sp;
+    cx.x[3] = gp;
+    cx.x[4] = tp;
+    cx.x[8] = fp;
+    println!("[kernel] gdb: kernel panicked, waiting for GDB");
+    session(&mut cx, Target::Kernel, SIGABRT);
+}
+
+/// Serve GDB until it resumes, kills or detaches.
+fn session(cx: &mut TrapContext, target: Target, signal: u8) -> Resume {
+    let mut input = [0u8; PACKET_SIZE];
+    if RESUMED.swap(false, Ordering::Relaxed) {
+        let mut reply = Reply::new();
+        reply.push_str("S");
+        reply.push_hex_u8(signal);
+        reply.send();
+    }
+    loop {
+        let packet = read_packet(&mut input);
+        let (&command, args) = match packet.split_first() {
+            Some(split) => split,
+            None => continue,
+        };
+        let mut reply = Reply::new();
+        match command {
+            b'?' => {
+                reply.push_str("S");
+                reply.push_hex_u8(signal);
+            }
+            b'g' => {
+                for i in 0..32 {
+                    reply.push_hex_le(reg(cx, i));
+                }
+                reply.push_hex_le(cx.sepc);
+            }
+            b'G' => {
+                for (i, value) in args.chunks(16).enumerate().take(REG_PC + 1) {
+                    if let Some(value) = parse_hex_le(value) {
+                        set_reg(cx, i, value);
+                    }
+                }
+                reply.push_str("OK");
+            }
+            b'p' => match parse_hex(args) {
+                Some(i) if i < REG_PC => reply.push_hex_le(reg(cx, i)),
+                Some(REG_PC) => reply.push_hex_le(cx.sepc),
+                // floating-point registers and CSRs are not saved
+                _ => reply.push_str("xxxxxxxxxxxxxxxx"),
+            },
+            b'P' => {
+                let mut split = args.splitn(2, |&c| c == b'=');
+                let i = split.next().and_then(parse_hex);
+                let value = split.next().and_then(parse_hex_le);
+                match (i, value) {
+                    (Some(i), Some(value)) if i <= REG_PC => {
+                        set_reg(cx, i, value);
+                        reply.push_str("OK");
+                    }
+                    _ => reply.push_str("E01"),
+                }
+            }
+            b'm' => match parse_addr_len(args).and_then(|(addr, len)| {
+                // each byte takes two hex digits in the reply
+                readable(target, addr, len.min(PACKET_SIZE / 2))
+            }) {
+                Some(bytes) => {
+                    for &byte in bytes {
+                        reply.push_hex_u8(byte);
+                    }
+                }
+                None => reply.push_str("E14"),
+            },
+            b'M' => {
+                if write_memory(target, args).is_some() {
+                    reply.push_str("OK");
+                } else {
+                    reply.push_str("E14");
+                }
+            }
+            b'c' | b's' => {
+                if let Target::Kernel = target {
+                    // there is nothing to resume after a panic
+                    reply.push_str("X");
+                    reply.push_hex_u8(signal);
+                    reply.send();
+                    return Resume::Continue;
+                }
+                if let Some(addr) = parse_hex(args) {
+                    cx.sepc = addr;
+                }
+                RESUMED.store(true, Ordering::Relaxed);
+                return if command == b'c' {
+                    Resume::Continue
+                } else {
+                    Resume::Step
+                };
+            }
+            b'k' => return Resume::Kill,
+            b'D' => {
+                reply.push_str("OK");
+                reply.send();
+                return Resume::Continue;
+            }
+            b'H' => reply.push_str("OK"),
+            b'q' => {
+                if args.starts_with(b"Supported") {
+                    reply.push_str("PacketSize=400");
+                } else if args == b"Attached" {
+                    reply.push_str("1");
+                }
+            }
+            _ => {}
+        }
+        reply.send();
+    }
+}
+
+/// Set register `i` in the GDB numbering; writes to `x0` are ignored.
+fn set_reg(cx: &mut TrapContext, i: usize, value: usize) {
+    match i {
+        0 => {}
+        REG_PC => cx.sepc = value,
+        _ => cx.x[i] = value,
+    }
+}
+
+/// Parse `addr,len`.
+fn parse_addr_len(args: &[u8]) -> Option<(usize, usize)> {
+    let mut split = args.splitn(2, |&c| c == b',');
+    let addr = parse_hex(split.next()?)?;
+    let len = parse_hex(split.next()?)?;
+    Some((addr, len))
+}
+
+/// Handle the arguments `addr,len:data` of `M`.
+fn write_memory(target: Target, args: &[u8]) -> Option<()> {
+    let mut split = args.splitn(2, |&c| c == b':');
+    let (addr, len) = parse_addr_len(split.next()?)?;
+    let data = split.next()?;
+    if data.len() != len.checked_mul(2)? {
+        return None;
+    }
+    let memory = writable(target, addr, len)?;
+    for (byte, pair) in memory.iter_mut().zip(data.chunks(2)) {
+        *byte = packet::hex_digit(pair[0])? << 4 | packet::hex_digit(pair[1])?;
+    }
+    // GDB writes breakpoints this way, so the instruction cache must see them
+    unsafe { asm!("fence.i") };
+    Some(())
+}
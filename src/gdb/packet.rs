@@ -0,0 +1,153 @@
+//! Packet framing of the GDB Remote Serial Protocol
+//!
+//! A packet is `$<data>#<checksum>`, where the checksum is the sum of the data
+//! bytes modulo 256 as two hex digits. Each packet is acknowledged with `+`,
+//! or `-` to ask for a retransmission.
+
+use crate::sbi::{console_getchar, console_putchar};
+
+/// Largest packet we accept, advertised to GDB in `qSupported`
+pub const PACKET_SIZE: usize = 0x400;
+
+const HEX_DIGITS: &[u8; 16] = b"0123456789abcdef";
+
+/// Wait for the next byte from the console.
+fn getchar() -> u8 {
+    loop {
+        if let Some(c) = console_getchar() {
+            return c;
+        }
+    }
+}
+
+/// Value of a hex digit
+pub fn hex_digit(c: u8) -> Option<u8> {
+    match c {
+        b'0'..=b'9' => Some(c - b'0'),
+        b'a'..=b'f' => Some(c - b'a' + 10),
+        b'A'..=b'F' => Some(c - b'A' + 10),
+        _ => None,
+    }
+}
+
+/// Parse a big-endian hex number such as an address or a length.
+pub fn parse_hex(s: &[u8]) -> Option<usize> {
+    if s.is_empty() || s.len() > 16 {
+        return None;
+    }
+    s.iter()
+        .try_fold(0usize, |acc, &c| Some(acc << 4 | hex_digit(c)? as usize))
+}
+
+/// Parse a register value, sent as its little-endian bytes in hex.
+pub fn parse_hex_le(s: &[u8]) -> Option<usize> {
+    if s.len() != 16 {
+        return None;
+    }
+    let mut value = 0;
+    for (i, pair) in s.chunks(2).enumerate() {
+        let byte = hex_digit(pair[0])? << 4 | hex_digit(pair[1])?;
+        value |= (byte as usize) << (8 * i);
+    }
+    Some(value)
+}
+
+/// Read the next well-formed packet into `buf` and acknowledge it.
+///
+/// Bytes outside a packet, such as the `+` acks of GDB or a `Ctrl-C` (0x03),
+/// are dropped. A packet with a bad checksum or too long for `buf` is answered
+/// with `-`, and GDB sends it again.
+pub fn read_packet(buf: &mut [u8; PACKET_SIZE]) -> &[u8] {
+    'packet: loop {
+        while getchar() != b'$' {}
+        let mut len = 0;
+        let mut sum: u8 = 0;
+        loop {
+            match getchar() {
+                b'#' => break,
+                // a new packet starts, the previous one was truncated
+                b'$' => continue 'packet,
+                c => {
+                    if len == buf.len() {
+                        console_putchar(b'-' as usize);
+                        continue 'packet;
+                    }
+                    buf[len] = c;
+                    len += 1;
+                    sum = sum.wrapping_add(c);
+                }
+            }
+        }
+        let high = hex_digit(getchar());
+        let low = hex_digit(getchar());
+        match (high, low) {
+            (Some(high), Some(low)) if high << 4 | low == sum => {
+                console_putchar(b'+' as usize);
+                return &buf[..len];
+            }
+            _ => console_putchar(b'-' as usize),
+        }
+    }
+}
+
+/// A reply being built, sent with `send`
+pub struct Reply {
+    buf: [u8; PACKET_SIZE],
+    len: usize,
+}
+
+impl Reply {
+    /// An empty reply, which tells GDB that a command is not supported
+    pub fn new() -> Self {
+        Self {
+            buf: [0; PACKET_SIZE],
+            len: 0,
+        }
+    }
+
+    /// Append raw bytes. Anything past `PACKET_SIZE` is dropped.
+    pub fn push_str(&mut self, s: &str) {
+        for &c in s.as_bytes() {
+            self.push(c);
+        }
+    }
+
+    fn push(&mut self, c: u8) {
+        if self.len < self.buf.len() {
+            self.buf[self.len] = c;
+            self.len += 1;
+        }
+    }
+
+    /// Append a byte as two hex digits.
+    pub fn push_hex_u8(&mut self, byte: u8) {
+        self.push(HEX_DIGITS[(byte >> 4) as usize]);
+        self.push(HEX_DIGITS[(byte & 0xf) as usize]);
+    }
+
+    /// Append a register value as its little-endian bytes in hex.
+    pub fn push_hex_le(&mut self, value: usize) {
+        for &byte in value.to_le_bytes().iter() {
+            self.push_hex_u8(byte);
+        }
+    }
+
+    /// Send the reply, again and again until GDB acknowledges it.
+    pub fn send(&self) {
+        let data = &self.buf[..self.len];
+        let sum = data.iter().fold(0u8, |sum, &c| sum.wrapping_add(c));
+        loop {
+            console_putchar(b'$' as usize);
+            for &c in data {
+                console_putchar(c as usize);
+            }
+            console_putchar(b'#' as usize);
+            console_putchar(HEX_DIGITS[(sum >> 4) as usize] as usize);
+            console_putchar(HEX_DIGITS[(sum & 0xf) as usize] as usize);
+            match getchar() {
+                b'-' => continue,
+                _ => return,
+            }
+        }
+    }
+}
@@ -0,0 +1,153 @@
+//! Software single-step
+//!
+//! The hart has no single-step mode we can use from S-mode, so a step places
+//! a `c.ebreak` at every address the current instruction may jump to, lets
+//! the app run, and puts the original instructions back at the next stop.
+//! A branch has two possible next instructions, anything else has one.
+
+use super::reg;
+use crate::mm::{read_user, write_user};
+use crate::sync::SpinMutex;
+use crate::trap::TrapContext;
+use core::arch::asm;
+use lazy_static::*;
+
+/// `c.ebreak`, two bytes long, so it fits over any instruction
+const C_EBREAK: u16 = 0x9002;
+
+/// The step breakpoints in place, and the app they were placed in
+struct StepBreakpoints {
+    task_id: usize,
+    /// address and original halfword of each breakpoint
+    points: [Option<(usize, u16)>; 2],
+}
+
+lazy_static! {
+    static ref STEP: SpinMutex<Option<StepBreakpoints>> = SpinMutex::new(None);
+}
+
+/// Sign-extend the lowest `bits` bits of `value`.
+fn sext(value: usize, bits: u32) -> usize {
+    let shift = usize::BITS - bits;
+    (((value << shift) as isize) >> shift) as usize
+}
+
+/// Bits `[lo, lo + len)` of `inst`, moved to bit `to`
+fn bits(inst: usize, lo: u32, len: u32, to: u32) -> usize {
+    ((inst >> lo) & ((1 << len) - 1)) << to
+}
+
+/// The addresses the instruction at `pc` may continue at,
+/// or `None` if the instruction cannot be read.
+fn next_pcs(cx: &TrapContext, pc: usize) -> Option<[Option<usize>; 2]> {
+    let low = read_user(pc as *const u16)? as usize;
+    if low & 0b11 != 0b11 {
+        return Some(next_pcs_compressed(cx, pc, low));
+    }
+    let inst = read_user(pc as *const u32)? as usize;
+    let rs1 = bits(inst, 15, 5, 0);
+    let next = pc.wrapping_add(4);
+    Some(match inst & 0x7f {
+        // JAL
+        0x6f => {
+            let imm = bits(inst, 31, 1, 20)
+                | bits(inst, 21, 10, 1)
+                | bits(inst, 20, 1, 11)
+                | bits(inst, 12, 8, 12);
+            [Some(pc.wrapping_add(sext(imm, 21))), None]
+        }
+        // JALR
+        0x67 => [Some(reg(cx, rs1).wrapping_add(sext(inst >> 20, 12)) & !1), None],
+        // BEQ, BNE, BLT, BGE, BLTU, BGEU
+        0x63 => {
+            let imm = bits(inst, 31, 1, 12)
+                | bits(inst, 7, 1, 11)
+                | bits(inst, 25, 6, 5)
+                | bits(inst, 8, 4, 1);
+            [Some(next), Some(pc.wrapping_add(sext(imm, 13)))]
+        }
+        _ => [Some(next), None],
+    })
+}
+
+/// `next_pcs` of a 16-bit instruction of the C extension
+fn next_pcs_compressed(cx: &TrapContext, pc: usize, inst: usize) -> [Option<usize>; 2] {
+    let next = pc.wrapping_add(2);
+    let funct3 = inst >> 13;
+    match (inst & 0b11, funct3) {
+        // C.J
+        (0b01, 0b101) => {
+            let imm = bits(inst, 12, 1, 11)
+                | bits(inst, 11, 1, 4)
+                | bits(inst, 9, 2, 8)
+                | bits(inst, 8, 1, 10)
+                | bits(inst, 7, 1, 6)
+                | bits(inst, 6, 1, 7)
+                | bits(inst, 3, 3, 1)
+                | bits(inst, 2, 1, 5);
+            [Some(pc.wrapping_add(sext(imm, 12))), None]
+        }
+        // C.BEQZ, C.BNEZ
+        (0b01, 0b110) | (0b01, 0b111) => {
+            let imm = bits(inst, 12, 1, 8)
+                | bits(inst, 10, 2, 3)
+                | bits(inst, 5, 2, 6)
+                | bits(inst, 3, 2, 1)
+                | bits(inst, 2, 1, 5);
+            [Some(next), Some(pc.wrapping_add(sext(imm, 9)))]
+        }
+        // C.JR, C.JALR: rs1 != 0 and rs2 == 0
+        (0b10, 0b100) if bits(inst, 7, 5, 0) != 0 && bits(inst, 2, 5, 0) == 0 => {
+            [Some(reg(cx, bits(inst, 7, 5, 0))), None]
+        }
+        _ => [Some(next), None],
+    }
+}
+
+/// Place breakpoints after the instruction at `cx.sepc` of app `task_id`.
+///
+/// A target the app cannot write, e.g. a jump out of its memory, gets no
+/// breakpoint; the app will fault there anyway.
+pub fn insert(cx: &TrapContext, task_id: usize) {
+    let targets = match next_pcs(cx, cx.sepc) {
+        Some(targets) => targets,
+        None => return,
+    };
+    let mut step = StepBreakpoints {
+        task_id,
+        points: [None; 2],
+    };
+    for (i, &target) in targets.iter().enumerate() {
+        let addr = match target {
+            // a branch to the next instruction needs only one breakpoint
+            Some(addr) if i == 0 || Some(addr) != targets[0] => addr,
+            _ => continue,
+        };
+        match read_user(addr as *const u16) {
+            Some(orig) if write_user(addr as *mut u16, C_EBREAK).is_some() => {
+                step.points[i] = Some((addr, orig));
+            }
+            _ => warn!("[kernel] gdb: cannot step to {:#x}", addr),
+        }
+    }
+    *STEP.lock() = Some(step);
+    unsafe { asm!("fence.i") };
+}
+
+/// Put back the instructions under the step breakpoints of app `task_id`.
+///
+/// Returns whether `sepc` is one of them, i.e. the app stopped because of a step.
+/// Breakpoints left in another app, which was killed during the step, are forgotten.
+pub fn remove(task_id: usize, sepc: usize) -> bool {
+    let step = match STEP.lock().take() {
+        Some(step) if step.task_id == task_id => step,
+        _ => return false,
+    };
+    let mut stepped = false;
+    for &(addr, orig) in step.points.iter().flatten() {
+        write_user(addr as *mut u16, orig);
+        stepped |= addr == sepc;
+    }
+    unsafe { asm!("fence.i") };
+    stepped
+}
@@ -0,0 +1,61 @@
+//! Kernel symbol lookup
+//!
+//! `build.rs` embeds the start address and the name of every kernel function
+//! in `ksyms.S`, taken from a previous link of the kernel. As `.text` comes first
+//! in the image, embedding the table does not move any function.
+//!
+//! If the kernel was built without `KERNEL_SYMBOLS`, the table is empty and
+//! addresses are printed raw.
+
+use core::fmt;
+
+extern "C" {
+    fn _ksym_num();
+    fn _ksym_addrs();
+    fn _ksym_name_offsets();
+    fn _ksym_names();
+    fn stext();
+    fn etext();
+}
+
+/// The function containing `addr`, and the offset of `addr` into it
+pub fn lookup(addr: usize) -> Option<(&'static str, usize)> {
+    if addr < stext as usize || addr >= etext as usize {
+        return None;
+    }
+    let (addrs, offsets) = unsafe {
+        let num = (_ksym_num as usize as *const usize).read_volatile();
+        (
+            core::slice::from_raw_parts(_ksym_addrs as usize as *const usize, num),
+            core::slice::from_raw_parts(_ksym_name_offsets as usize as *const usize, num),
+        )
+    };
+    // the last symbol starting at or before `addr`
+    let index = match addrs.binary_search(&addr) {
+        Ok(index) => index,
+        Err(0) => return None,
+        Err(index) => index - 1,
+    };
+    let name = unsafe {
+        let start = (_ksym_names as usize + offsets[index]) as *const u8;
+        let mut len = 0;
+        while start.add(len).read_volatile() != 0 {
+            len += 1;
+        }
+        core::str::from_utf8(core::slice::from_raw_parts(start, len)).ok()?
+    };
+    Some((name, addr - addrs[index]))
+}
+
+/// An address displayed as `0x80200a3c <os::task::processor::run_tasks+0x5c>`,
+/// or just the address if it is not in a known kernel function
+pub struct Symbolized(pub usize);
+
+impl fmt::Display for Symbolized {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match lookup(self.0) {
+            Some((name, offset)) => write!(f, "{:#x} <{}+{:#x}>", self.0, name, offset),
+            None => write!(f, "{:#x}", self.0),
+        }
+    }
+}
@@ -1,10 +1,41 @@
 //! The panic handler
 
+use crate::qemu::{exit, ExitStatus};
+use crate::stack_trace::print_stack_trace;
 use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// Set by the first panic, so that a panic while reporting a panic
+/// exits right away instead of recursing.
+static PANICKING: AtomicBool = AtomicBool::new(false);
 
 #[panic_handler]
 /// panic handler
-fn panic(_info: &PanicInfo) -> ! {
-    println!("[kernel] Panicked");
-    loop {}
+///
+/// Print the message, the location and a stack trace, then
+/// exit QEMU with `ExitStatus::KernelPanic`, so a panic is not mistaken for a finished batch.
+fn panic(info: &PanicInfo) -> ! {
+    if PANICKING.swap(true, Ordering::Relaxed) {
+        println!("[kernel] Panicked while panicking");
+        exit(ExitStatus::KernelPanic)
+    }
+    // nothing here may panic, or the location and the stack trace are lost
+    match (info.location(), info.message()) {
+        (Some(location), Some(message)) => println!(
+            "[kernel] Panicked at {}:{} {}",
+            location.file(),
+            location.line(),
+            message
+        ),
+        (Some(location), None) => {
+            println!("[kernel] Panicked at {}:{}", location.file(), location.line())
+        }
+        (None, Some(message)) => println!("[kernel] Panicked: {}", message),
+        (None, None) => println!("[kernel] Panicked"),
+    }
+    print_stack_trace();
+    if crate::gdb::enabled() {
+        crate::gdb::enter_on_panic();
+    }
+    exit(ExitStatus::KernelPanic)
 }
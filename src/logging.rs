@@ -1,5 +1,6 @@
 //! Global logger
 
+use crate::config::env_choice;
 use log::{Level, LevelFilter, Log, Metadata, Record};
 
 /// a simple logger
@@ -34,12 +35,15 @@ impl Log for SimpleLogger {
 pub fn init() {
     static LOGGER: SimpleLogger = SimpleLogger;
     log::set_logger(&LOGGER).unwrap();
-    log::set_max_level(match option_env!("LOG") {
-        Some("ERROR") => LevelFilter::Error,
-        Some("WARN") => LevelFilter::Warn,
-        Some("INFO") => LevelFilter::Info,
-        Some("DEBUG") => LevelFilter::Debug,
-        Some("TRACE") => LevelFilter::Trace,
-        _ => LevelFilter::Off,
-    });
+    log::set_max_level(env_choice(
+        option_env!("LOG"),
+        &[
+            ("ERROR", LevelFilter::Error),
+            ("WARN", LevelFilter::Warn),
+            ("INFO", LevelFilter::Info),
+            ("DEBUG", LevelFilter::Debug),
+            ("TRACE", LevelFilter::Trace),
+        ],
+        LevelFilter::Off,
+    ));
 }
@@ -5,10 +5,15 @@
 #![no_std]
 #![no_main]
 #![allow(dead_code)]
+#![feature(panic_info_message)]
+#![feature(alloc_error_handler)]
+#![feature(asm_const)]
 
 #[macro_use]
 extern crate log;
 
+extern crate alloc;
+
 use log::*;
 
 #[macro_use]
@@ -16,14 +21,31 @@ mod console;
 mod lang_items;
 mod sbi;
 mod logging;
+mod mm;
+mod qemu;
 mod batch;
+mod config;
+mod elf;
+mod fdt;
+mod gdb;
+mod ksym;
+mod report;
+mod smp;
+mod stack_trace;
+mod timer;
 
 pub mod syscall;
 pub mod sync;
+pub mod task;
 pub mod trap;
 
-core::arch::global_asm!(include_str!("entry.asm"));
+core::arch::global_asm!(
+    include_str!("entry.asm"),
+    max_harts = const config::MAX_HARTS,
+    boot_stack_size = const config::BOOT_STACK_SIZE,
+);
 core::arch::global_asm!(include_str!("link_app.S"));
+core::arch::global_asm!(include_str!("ksyms.S"));
 
 /// Clear BSS segment
 /// 
@@ -48,7 +70,11 @@ fn clear_bss() {
 
 #[no_mangle]
 /// The entry point
-fn rust_main() {
+///
+/// The firmware passes the id of this hart in `a0` and the address of
+/// the device tree blob in `a1`, which `entry.asm` leaves untouched.
+/// `entry.asm` jumps here without a return address, so this never returns.
+extern "C" fn rust_main(_hart_id: usize, dtb: usize) -> ! {
     clear_bss();
     extern "C" {
         fn stext(); // begin addr of text segment
@@ -63,8 +89,12 @@ fn rust_main() {
         fn boot_stack_top(); // stack top
     }
     clear_bss();
+    mm::init_heap();
+    // the other harts wait here until the first task is switched in
+    smp::lock_kernel();
     logging::init();
     println!("[kernel] Hello, world!");
+    sbi::print_info();
     trace!(
         "[kernel] .text [{:#x}, {:#x})",
         stext as usize, etext as usize
@@ -82,7 +112,37 @@ fn rust_main() {
         boot_stack_top as usize, boot_stack as usize
     );
     error!("[kernel] .bss [{:#x}, {:#x})", sbss as usize, ebss as usize);
+    timer::init(dtb);
     trap::init();
     batch::init();
-    batch::run_next_app();
+    trap::enable_timer_interrupt();
+    smp::start_secondary_harts();
+    task::run_tasks();
+}
+
+#[no_mangle]
+/// The entry point when the boot hart has an id of at least `MAX_HARTS`,
+/// which the kernel has no boot stack and no `Processor` for.
+///
+/// Nothing is initialized yet, so just say why we stop.
+extern "C" fn rust_main_unsupported_hart(hart_id: usize) -> ! {
+    println!(
+        "[kernel] boot hart {} is beyond MAX_HARTS = {}, shutting down",
+        hart_id,
+        config::MAX_HARTS
+    );
+    qemu::exit(qemu::ExitStatus::KernelPanic)
+}
+
+#[no_mangle]
+/// The entry point of the other harts, started by `smp::start_secondary_harts`
+///
+/// Everything global is set up by the boot hart already; only the CSRs of
+/// this hart are left.
+extern "C" fn rust_main_secondary(hart_id: usize) -> ! {
+    smp::lock_kernel();
+    trap::init();
+    trap::enable_timer_interrupt();
+    info!("[kernel] hart {} is up", hart_id);
+    task::run_tasks();
 }
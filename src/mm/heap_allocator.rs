@@ -0,0 +1,209 @@
+//! The kernel heap
+//!
+//! A first-fit linked-list allocator over `[KERNEL_HEAP_BASE, KERNEL_HEAP_BASE + KERNEL_HEAP_SIZE)`,
+//! the memory right after the app slots.
+//! Free blocks are kept in a list sorted by address, so a freed block is
+//! merged with its free neighbours and the heap does not fragment for good.
+//! Every block is a multiple of `BLOCK_ALIGN` bytes, and a free block stores
+//! its size and the next free block in its first bytes.
+
+use crate::config::{KERNEL_HEAP_BASE, KERNEL_HEAP_SIZE};
+use crate::sync::SpinMutex;
+use core::alloc::{GlobalAlloc, Layout};
+use core::fmt;
+use core::mem::size_of;
+use core::ptr::null_mut;
+
+/// Header of a free block, stored in the block itself
+struct FreeBlock {
+    /// size of the block in bytes, header included
+    size: usize,
+    /// next free block, at a higher address
+    next: *mut FreeBlock,
+}
+
+/// Alignment and size granularity of every block, so any block can hold a header
+const BLOCK_ALIGN: usize = size_of::<FreeBlock>();
+
+/// Allocator statistics
+#[derive(Debug, Clone, Copy)]
+pub struct HeapStats {
+    /// bytes in the heap
+    pub total: usize,
+    /// bytes handed out, after rounding to `BLOCK_ALIGN`
+    pub used: usize,
+    /// highest `used` so far
+    pub peak: usize,
+    /// successful allocations
+    pub allocs: usize,
+    /// deallocations
+    pub frees: usize,
+    /// allocations that found no room
+    pub failures: usize,
+}
+
+impl fmt::Display for HeapStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "used {:#x} / {:#x} bytes, peak {:#x}, {} allocs, {} frees, {} failures",
+            self.used, self.total, self.peak, self.allocs, self.frees, self.failures
+        )
+    }
+}
+
+/// The free list and the statistics
+struct LinkedListHeap {
+    /// free block with the lowest address
+    head: *mut FreeBlock,
+    stats: HeapStats,
+}
+
+unsafe impl Send for LinkedListHeap {}
+
+fn align_up(addr: usize, align: usize) -> usize {
+    (addr + align - 1) & !(align - 1)
+}
+
+/// Size and alignment of the block backing `layout`
+fn block_layout(layout: &Layout) -> (usize, usize) {
+    let size = align_up(layout.size().max(BLOCK_ALIGN), BLOCK_ALIGN);
+    (size, layout.align().max(BLOCK_ALIGN))
+}
+
+impl LinkedListHeap {
+    const fn new() -> Self {
+        Self {
+            head: null_mut(),
+            stats: HeapStats {
+                total: 0,
+                used: 0,
+                peak: 0,
+                allocs: 0,
+                frees: 0,
+                failures: 0,
+            },
+        }
+    }
+
+    /// Hand `[start, start + size)` to the heap.
+    ///
+    /// # Safety
+    ///
+    /// The region must be unused, and must not be given twice.
+    unsafe fn init(&mut self, start: usize, size: usize) {
+        let aligned = align_up(start, BLOCK_ALIGN);
+        let size = (start + size - aligned) & !(BLOCK_ALIGN - 1);
+        self.stats.total += size;
+        self.insert(aligned, size);
+    }
+
+    /// Put the free block `[addr, addr + size)` into the list,
+    /// merging it with the blocks right before and after it.
+    unsafe fn insert(&mut self, addr: usize, size: usize) {
+        let block = addr as *mut FreeBlock;
+        let mut prev: *mut FreeBlock = null_mut();
+        let mut next = self.head;
+        while !next.is_null() && (next as usize) < addr {
+            prev = next;
+            next = (*next).next;
+        }
+        block.write(FreeBlock { size, next });
+        if !next.is_null() && addr + size == next as usize {
+            (*block).size += (*next).size;
+            (*block).next = (*next).next;
+        }
+        if prev.is_null() {
+            self.head = block;
+        } else if prev as usize + (*prev).size == addr {
+            (*prev).size += (*block).size;
+            (*prev).next = (*block).next;
+        } else {
+            (*prev).next = block;
+        }
+    }
+
+    /// Take the first free block that fits, first fit.
+    ///
+    /// Whatever is left of the block before and after the allocation
+    /// goes back to the free list.
+    unsafe fn alloc(&mut self, layout: Layout) -> *mut u8 {
+        let (size, align) = block_layout(&layout);
+        let mut prev: *mut FreeBlock = null_mut();
+        let mut block = self.head;
+        while !block.is_null() {
+            let start = block as usize;
+            let end = start + (*block).size;
+            let addr = align_up(start, align);
+            if addr + size <= end {
+                // unlink the block, then give back the unused parts
+                let next = (*block).next;
+                if prev.is_null() {
+                    self.head = next;
+                } else {
+                    (*prev).next = next;
+                }
+                if addr > start {
+                    self.insert(start, addr - start);
+                }
+                if addr + size < end {
+                    self.insert(addr + size, end - addr - size);
+                }
+                self.stats.used += size;
+                self.stats.peak = self.stats.peak.max(self.stats.used);
+                self.stats.allocs += 1;
+                return addr as *mut u8;
+            }
+            prev = block;
+            block = (*block).next;
+        }
+        self.stats.failures += 1;
+        null_mut()
+    }
+
+    unsafe fn dealloc(&mut self, ptr: *mut u8, layout: Layout) {
+        let (size, _) = block_layout(&layout);
+        self.insert(ptr as usize, size);
+        self.stats.used -= size;
+        self.stats.frees += 1;
+    }
+}
+
+/// The `#[global_allocator]`: a `LinkedListHeap` behind a lock
+pub struct LockedHeap(SpinMutex<LinkedListHeap>);
+
+unsafe impl GlobalAlloc for LockedHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.0.lock().alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.0.lock().dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+/// heap allocator instance
+static HEAP_ALLOCATOR: LockedHeap = LockedHeap(SpinMutex::new(LinkedListHeap::new()));
+
+/// Give the heap region to the allocator. Called once, before anything is allocated.
+pub fn init_heap() {
+    unsafe {
+        HEAP_ALLOCATOR.0.lock().init(KERNEL_HEAP_BASE, KERNEL_HEAP_SIZE);
+    }
+}
+
+/// Current allocator statistics
+pub fn heap_stats() -> HeapStats {
+    HEAP_ALLOCATOR.0.lock().stats
+}
+
+#[alloc_error_handler]
+/// panic when heap allocation error occurs, with the layout that did not fit
+pub fn handle_alloc_error(layout: Layout) -> ! {
+    panic!(
+        "Heap allocation error, layout = {:?}, heap: {}",
+        layout,
+        heap_stats()
+    );
+}
@@ -0,0 +1,11 @@
+//! Memory management
+//!
+//! There is no paging yet: apps run on physical addresses, in their own slot.
+//! This module keeps the kernel from trusting addresses handed in by apps,
+//! and provides the kernel heap behind `alloc`.
+
+mod heap_allocator;
+mod uaccess;
+
+pub use heap_allocator::{heap_stats, init_heap, HeapStats};
+pub use uaccess::{read_user, user_slice, user_slice_mut, write_user};
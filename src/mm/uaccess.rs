@@ -0,0 +1,65 @@
+//! Access to user memory from the kernel
+//!
+//! An app may only pass buffers inside its own memory: its slot, where its image
+//! is loaded, or its user stack. Anything else, including kernel memory and the
+//! memory of other apps, is refused and the syscall returns `-EFAULT`.
+
+use crate::batch::app_user_ranges;
+use crate::task::current_task_id;
+use core::mem::size_of;
+
+/// Whether `[addr, addr + len)` lies inside the memory of the current app
+fn in_current_app(addr: usize, len: usize) -> bool {
+    let end = match addr.checked_add(len) {
+        Some(end) => end,
+        None => return false,
+    };
+    app_user_ranges(current_task_id())
+        .iter()
+        .any(|&(start, limit)| addr >= start && end <= limit)
+}
+
+/// The user buffer `[ptr, ptr + len)`, if the current app owns it.
+///
+/// An empty buffer is always accepted, whatever `ptr` is.
+pub fn user_slice(ptr: *const u8, len: usize) -> Option<&'static [u8]> {
+    if len == 0 {
+        return Some(&[]);
+    }
+    if !in_current_app(ptr as usize, len) {
+        return None;
+    }
+    Some(unsafe { core::slice::from_raw_parts(ptr, len) })
+}
+
+/// The user buffer `[ptr, ptr + len)` for writing, if the current app owns it.
+///
+/// An empty buffer is always accepted, whatever `ptr` is.
+pub fn user_slice_mut(ptr: *mut u8, len: usize) -> Option<&'static mut [u8]> {
+    if len == 0 {
+        return Some(&mut []);
+    }
+    if !in_current_app(ptr as usize, len) {
+        return None;
+    }
+    Some(unsafe { core::slice::from_raw_parts_mut(ptr, len) })
+}
+
+/// Read a `T` from user memory, if the current app owns it.
+/// The pointer does not need to be aligned.
+pub fn read_user<T: Copy>(ptr: *const T) -> Option<T> {
+    if !in_current_app(ptr as usize, size_of::<T>()) {
+        return None;
+    }
+    Some(unsafe { ptr.read_unaligned() })
+}
+
+/// Write `value` to user memory, if the current app owns it.
+/// The pointer does not need to be aligned.
+pub fn write_user<T>(ptr: *mut T, value: T) -> Option<()> {
+    if !in_current_app(ptr as usize, size_of::<T>()) {
+        return None;
+    }
+    unsafe { ptr.write_unaligned(value) };
+    Some(())
+}
@@ -0,0 +1,41 @@
+//! QEMU virt board specifics
+//!
+//! QEMU virt has a `sifive_test` device at `VIRT_TEST`. Writing to it ends QEMU
+//! with a chosen exit status, which lets `test.sh` and CI tell why the kernel stopped.
+
+/// Address of the `sifive_test` device
+const VIRT_TEST: usize = 0x100000;
+/// Exit QEMU with status 0
+const FINISHER_PASS: u32 = 0x5555;
+/// Exit QEMU with the status in the upper 16 bits
+const FINISHER_FAIL: u32 = 0x3333;
+
+/// Exit status of QEMU, i.e., of `test.sh`
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[repr(u32)]
+pub enum ExitStatus {
+    /// Every app exited with code 0
+    Success = 0,
+    /// An app exited with a non-zero code or could not be loaded,
+    /// or the app table was refused at boot
+    AppFailed = 1,
+    /// An app was killed by the kernel: exception, unsupported syscall or time limit
+    AppFaulted = 2,
+    /// The kernel panicked
+    KernelPanic = 3,
+}
+
+/// End QEMU with `status`.
+///
+/// If the test device does not stop us (e.g., not running on QEMU virt),
+/// fall back to an SBI shutdown, which only tells success from failure.
+pub fn exit(status: ExitStatus) -> ! {
+    let value = match status {
+        ExitStatus::Success => FINISHER_PASS,
+        _ => FINISHER_FAIL | (status as u32) << 16,
+    };
+    unsafe {
+        (VIRT_TEST as *mut u32).write_volatile(value);
+    }
+    crate::sbi::shutdown(status != ExitStatus::Success)
+}
@@ -0,0 +1,185 @@
+//! End-of-batch report
+//!
+//! Each app that leaves the batch, normally or not, is recorded here.
+//! When the batch is over, `print_summary` prints a table for humans and
+//! one `BATCH_RESULT` line per app plus a `BATCH_SUMMARY` line for CI to scrape:
+//!
+//! ```text
+//! BATCH_RESULT id=0 name=hello status=exited code=0 time_us=1520
+//! BATCH_RESULT id=1 name=bad status=killed cause=store_fault sepc=0x80400010 stval=0x0 time_us=310
+//! BATCH_RESULT id=2 name=spin status=killed cause=time_limit_exceeded limit_ms=10000 time_us=10012873
+//! BATCH_SUMMARY total=3 passed=1 failed=2
+//! ```
+
+use crate::config::MAX_APP_NUM;
+use crate::qemu::ExitStatus;
+use crate::sync::SpinMutex;
+use lazy_static::*;
+use riscv::register::scause::Exception;
+
+/// How an app left the batch
+#[derive(Clone, Copy)]
+pub enum Outcome {
+    /// The app called `sys_exit` with this exit code
+    Exited(i32),
+    /// The kernel killed the app because of an exception
+    Killed {
+        /// the exception
+        cause: Exception,
+        /// address of the faulting instruction
+        sepc: usize,
+        /// the faulting address or instruction, depending on `cause`
+        stval: usize,
+    },
+    /// The kernel killed the app because it made a syscall we do not support,
+    /// see `UNKNOWN_SYSCALL_POLICY`
+    BadSyscall {
+        /// the syscall number
+        syscall_id: usize,
+        /// address of the `ecall`
+        sepc: usize,
+    },
+    /// The kernel killed the app because it used more CPU time than its limit
+    TimeLimitExceeded {
+        /// the limit in milliseconds
+        limit_ms: usize,
+    },
+    /// The app image was rejected by the loader and never ran
+    LoadFailed,
+}
+
+impl Outcome {
+    /// An app passes if it exited with code 0
+    pub fn passed(&self) -> bool {
+        matches!(self, Outcome::Exited(0))
+    }
+}
+
+/// Human-readable and machine-readable names of an exception
+pub fn exception_names(cause: Exception) -> (&'static str, &'static str) {
+    match cause {
+        Exception::InstructionMisaligned => ("misaligned instruction fetch", "instruction_misaligned"),
+        Exception::InstructionFault => ("instruction access fault", "instruction_fault"),
+        Exception::IllegalInstruction => ("illegal instruction", "illegal_instruction"),
+        Exception::Breakpoint => ("breakpoint", "breakpoint"),
+        Exception::LoadFault => ("load fault", "load_fault"),
+        Exception::StoreMisaligned => ("misaligned store", "store_misaligned"),
+        Exception::StoreFault => ("store fault", "store_fault"),
+        Exception::UserEnvCall => ("environment call", "user_env_call"),
+        Exception::InstructionPageFault => ("instruction page fault", "instruction_page_fault"),
+        Exception::LoadPageFault => ("load page fault", "load_page_fault"),
+        Exception::StorePageFault => ("store page fault", "store_page_fault"),
+        _ => ("unknown exception", "unknown"),
+    }
+}
+
+/// What we know about one app that has left the batch
+#[derive(Clone, Copy)]
+struct Record {
+    name: &'static str,
+    outcome: Outcome,
+    time_us: usize,
+}
+
+/// Records of all apps, indexed by app id
+struct Report {
+    records: [Option<Record>; MAX_APP_NUM],
+}
+
+lazy_static! {
+    /// The global report, filled in as apps leave the batch.
+    static ref REPORT: SpinMutex<Report> = SpinMutex::new(Report {
+        records: [None; MAX_APP_NUM],
+    });
+}
+
+/// Record that app `app_id` left the batch with `outcome` after running `time_us` microseconds
+pub fn record(app_id: usize, name: &'static str, outcome: Outcome, time_us: usize) {
+    REPORT.lock().records[app_id] = Some(Record {
+        name,
+        outcome,
+        time_us,
+    });
+}
+
+/// The exit status telling how the batch went.
+///
+/// An app killed by the kernel outweighs an app that merely failed.
+pub fn exit_status() -> ExitStatus {
+    let report = REPORT.lock();
+    let outcomes = || report.records.iter().flatten().map(|record| record.outcome);
+    let killed = |outcome: Outcome| {
+        matches!(
+            outcome,
+            Outcome::Killed { .. } | Outcome::BadSyscall { .. } | Outcome::TimeLimitExceeded { .. }
+        )
+    };
+    if outcomes().any(killed) {
+        ExitStatus::AppFaulted
+    } else if outcomes().all(|outcome| outcome.passed()) {
+        ExitStatus::Success
+    } else {
+        ExitStatus::AppFailed
+    }
+}
+
+/// Print the summary table, then the lines for CI
+pub fn print_summary() {
+    let report = REPORT.lock();
+    let records = || {
+        report.records
+            .iter()
+            .enumerate()
+            .filter_map(|(id, record)| record.map(|record| (id, record)))
+    };
+    let total = records().count();
+    let passed = records().filter(|(_, record)| record.outcome.passed()).count();
+
+    println!("[kernel] ========== batch summary ==========");
+    println!("[kernel] {:>3}  {:<16} {:>10}  outcome", "id", "name", "time(us)");
+    for (id, record) in records() {
+        print!("[kernel] {:>3}  {:<16} {:>10}  ", id, record.name, record.time_us);
+        match record.outcome {
+            Outcome::Exited(code) => println!("exited with code {}", code),
+            Outcome::Killed { cause, sepc, stval } => println!(
+                "killed by {}, sepc = {:#x}, stval = {:#x}",
+                exception_names(cause).0,
+                sepc,
+                stval
+            ),
+            Outcome::BadSyscall { syscall_id, sepc } => println!(
+                "killed by unsupported syscall {}, sepc = {:#x}",
+                syscall_id, sepc
+            ),
+            Outcome::TimeLimitExceeded { limit_ms } => {
+                println!("killed, time limit exceeded ({} ms)", limit_ms)
+            }
+            Outcome::LoadFailed => println!("failed to load"),
+        }
+    }
+    println!("[kernel] {} app(s), {} passed, {} failed", total, passed, total - passed);
+
+    for (id, record) in records() {
+        print!("BATCH_RESULT id={} name={} ", id, record.name);
+        match record.outcome {
+            Outcome::Exited(code) => print!("status=exited code={}", code),
+            Outcome::Killed { cause, sepc, stval } => print!(
+                "status=killed cause={} sepc={:#x} stval={:#x}",
+                exception_names(cause).1,
+                sepc,
+                stval
+            ),
+            Outcome::BadSyscall { syscall_id, sepc } => print!(
+                "status=killed cause=unsupported_syscall syscall={} sepc={:#x}",
+                syscall_id, sepc
+            ),
+            Outcome::TimeLimitExceeded { limit_ms } => print!(
+                "status=killed cause=time_limit_exceeded limit_ms={}",
+                limit_ms
+            ),
+            Outcome::LoadFailed => print!("status=load_failed"),
+        }
+        println!(" time_us={}", record.time_us);
+    }
+    println!("BATCH_SUMMARY total={} passed={} failed={}", total, passed, total - passed);
+}
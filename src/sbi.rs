@@ -1,40 +1,319 @@
 //! SBI (Supervisor Binary Interface) calls wrappers
-//! 
+//!
 //! SBI is the interface between an **operating system** (running is **supervisor mode**, S-mode)
 //! and the **firmware/hypervisor** (running in **machine mode**, M-mode).
 //! It allows the OS to request privileged operations.
+//!
+//! Since SBI v0.2, a call names an extension (EID in `a7`) and a function in it
+//! (FID in `a6`), and returns an error code and a value (`SbiRet`).
+//! The console, the timer and shutdown go through the DBCN, TIME and SRST
+//! extensions when the firmware has them, which is asked once with
+//! `probe_extension`. Otherwise, e.g. on v0.1 firmware, we fall back to
+//! the legacy calls, which are deprecated but still implemented by
+//! the bundled RustSBI and by OpenSBI.
 
-/// SBI code for console putchar
+use core::sync::atomic::{AtomicU8, Ordering};
+
+/// Legacy SBI code for set timer
+const SBI_SET_TIMER: usize = 0;
+
+/// Legacy SBI code for console putchar
 const SBI_CONSOLE_PUTCHAR: usize = 1;
 
-/// SBI code for shutdown
+/// Legacy SBI code for console getchar
+const SBI_CONSOLE_GETCHAR: usize = 2;
+
+/// Legacy SBI code for shutdown
 const SBI_SHUTDOWN: usize = 8;
 
-/// General sbi call
-/// 
-/// Note that `x16` (a6) must be `0` for SBI calls.
-fn sbi_call(which: usize, arg0: usize, arg1: usize, arg2: usize) -> usize {
-    let mut ret;
+/// SBI Base extension, always present since v0.2
+const SBI_EXT_BASE: usize = 0x10;
+/// Base: get the SBI specification version
+const BASE_GET_SPEC_VERSION: usize = 0;
+/// Base: get the SBI implementation id
+const BASE_GET_IMPL_ID: usize = 1;
+/// Base: get the SBI implementation version
+const BASE_GET_IMPL_VERSION: usize = 2;
+/// Base: probe an extension
+const BASE_PROBE_EXTENSION: usize = 3;
+
+/// SBI Timer extension (TIME)
+const SBI_EXT_TIME: usize = 0x54494D45;
+/// TIME: program the next timer event
+const TIME_SET_TIMER: usize = 0;
+
+/// SBI Debug Console extension (DBCN)
+const SBI_EXT_DBCN: usize = 0x4442434E;
+/// DBCN: write bytes
+const DBCN_CONSOLE_WRITE: usize = 0;
+/// DBCN: read bytes, without waiting
+const DBCN_CONSOLE_READ: usize = 1;
+/// DBCN: write a single byte
+const DBCN_CONSOLE_WRITE_BYTE: usize = 2;
+
+/// SBI Hart State Management extension (HSM)
+const SBI_EXT_HSM: usize = 0x48534D;
+/// HSM: start a stopped hart
+const HSM_HART_START: usize = 0;
+/// HSM: get the state of a hart
+const HSM_HART_GET_STATUS: usize = 2;
+
+/// SBI System Reset extension (SRST)
+const SBI_EXT_SRST: usize = 0x53525354;
+/// SRST: reset the system
+const SRST_SYSTEM_RESET: usize = 0;
+/// SRST reset type: shutdown
+const SRST_TYPE_SHUTDOWN: usize = 0;
+/// SRST reset reason: no reason
+const SRST_REASON_NONE: usize = 0;
+/// SRST reset reason: system failure
+const SRST_REASON_FAILURE: usize = 1;
+
+/// SBI error code: success
+const SBI_SUCCESS: isize = 0;
+
+/// Result of an SBI call, from `a0` and `a1`
+#[derive(Debug, Clone, Copy)]
+pub struct SbiRet {
+    /// `SBI_SUCCESS` or a negative error code
+    pub error: isize,
+    /// the result, if `error` is `SBI_SUCCESS`
+    pub value: usize,
+}
+
+impl SbiRet {
+    /// Whether the call succeeded
+    pub fn is_ok(&self) -> bool {
+        self.error == SBI_SUCCESS
+    }
+}
+
+/// SBI call with the v0.2+ convention: extension `eid`, function `fid`
+fn sbi_call(eid: usize, fid: usize, arg0: usize, arg1: usize, arg2: usize) -> SbiRet {
+    let (error, value): (isize, usize);
     unsafe {
         core::arch::asm!(
             "ecall",
-            inlateout("x10") arg0 => ret,
-            in("x11") arg1,
+            inlateout("x10") arg0 => error,
+            inlateout("x11") arg1 => value,
             in("x12") arg2,
-            in("x16") 0,
-            in("x17") which,
+            in("x16") fid,
+            in("x17") eid,
         );
     }
-    ret
+    SbiRet { error, value }
+}
+
+/// Legacy (v0.1) sbi call, which only returns `a0`
+///
+/// `x16` (a6) is set to `0`, as some firmware expects.
+fn sbi_call_legacy(which: usize, arg0: usize, arg1: usize, arg2: usize) -> usize {
+    sbi_call(which, 0, arg0, arg1, arg2).error as usize
+}
+
+/// The SBI specification version as `(major, minor)`,
+/// or `None` for v0.1 firmware, which has no Base extension.
+pub fn spec_version() -> Option<(usize, usize)> {
+    let ret = sbi_call(SBI_EXT_BASE, BASE_GET_SPEC_VERSION, 0, 0, 0);
+    // bit 31 must be 0; v0.1 firmware returns an error or garbage here
+    if !ret.is_ok() || ret.value >> 31 != 0 {
+        return None;
+    }
+    Some(((ret.value >> 24) & 0x7f, ret.value & 0xff_ffff))
+}
+
+/// The SBI implementation id and version, e.g. `(4, _)` for RustSBI
+pub fn impl_id() -> Option<(usize, usize)> {
+    spec_version()?;
+    let id = sbi_call(SBI_EXT_BASE, BASE_GET_IMPL_ID, 0, 0, 0);
+    let version = sbi_call(SBI_EXT_BASE, BASE_GET_IMPL_VERSION, 0, 0, 0);
+    Some((id.value, version.value))
+}
+
+/// Name of an SBI implementation id
+pub fn impl_name(id: usize) -> &'static str {
+    match id {
+        0 => "BBL",
+        1 => "OpenSBI",
+        2 => "Xvisor",
+        3 => "KVM",
+        4 => "RustSBI",
+        5 => "Diosix",
+        6 => "Coffer",
+        7 => "Xen",
+        8 => "PolarFire HSS",
+        _ => "unknown",
+    }
+}
+
+/// Whether the firmware implements extension `eid`
+pub fn probe_extension(eid: usize) -> bool {
+    if spec_version().is_none() {
+        return false;
+    }
+    let ret = sbi_call(SBI_EXT_BASE, BASE_PROBE_EXTENSION, eid, 0, 0);
+    ret.is_ok() && ret.value != 0
+}
+
+/// An extension whose availability is probed on first use
+struct Extension {
+    eid: usize,
+    /// `UNKNOWN`, `ABSENT` or `PRESENT`
+    state: AtomicU8,
+}
+
+const UNKNOWN: u8 = 0;
+const ABSENT: u8 = 1;
+const PRESENT: u8 = 2;
+
+impl Extension {
+    const fn new(eid: usize) -> Self {
+        Self {
+            eid,
+            state: AtomicU8::new(UNKNOWN),
+        }
+    }
+
+    fn available(&self) -> bool {
+        match self.state.load(Ordering::Relaxed) {
+            PRESENT => true,
+            ABSENT => false,
+            _ => {
+                let present = probe_extension(self.eid);
+                let state = if present { PRESENT } else { ABSENT };
+                self.state.store(state, Ordering::Relaxed);
+                present
+            }
+        }
+    }
+}
+
+static TIME: Extension = Extension::new(SBI_EXT_TIME);
+static DBCN: Extension = Extension::new(SBI_EXT_DBCN);
+static SRST: Extension = Extension::new(SBI_EXT_SRST);
+static HSM: Extension = Extension::new(SBI_EXT_HSM);
+
+/// Print what the firmware is and which extensions we use.
+pub fn print_info() {
+    match (spec_version(), impl_id()) {
+        (Some((major, minor)), Some((id, version))) => println!(
+            "[kernel] SBI v{}.{}, {} (id {}) version {:#x}",
+            major,
+            minor,
+            impl_name(id),
+            id,
+            version
+        ),
+        _ => println!("[kernel] SBI v0.1, legacy calls only"),
+    }
+    let yes_no = |ext: &Extension| if ext.available() { "yes" } else { "no, legacy" };
+    println!(
+        "[kernel] SBI extensions: TIME {}, DBCN {}, SRST {}, HSM {}",
+        yes_no(&TIME),
+        yes_no(&DBCN),
+        yes_no(&SRST),
+        if HSM.available() { "yes" } else { "no, single hart" }
+    );
+}
+
+/// Whether the firmware can start other harts
+pub fn hsm_available() -> bool {
+    HSM.available()
+}
+
+/// Use sbi call to start hart `hart_id` at `start_addr` in S-mode,
+/// with its hart id in `a0` and `opaque` in `a1`.
+pub fn hart_start(hart_id: usize, start_addr: usize, opaque: usize) -> SbiRet {
+    sbi_call(SBI_EXT_HSM, HSM_HART_START, hart_id, start_addr, opaque)
+}
+
+/// Use sbi call to get the HSM state of hart `hart_id`, e.g. 1 for stopped.
+/// An error means there is no such hart.
+pub fn hart_get_status(hart_id: usize) -> SbiRet {
+    sbi_call(SBI_EXT_HSM, HSM_HART_GET_STATUS, hart_id, 0, 0)
 }
 
 /// use sbi call to putchar in console (qemu uart handler)
 pub fn console_putchar(c: usize) {
-    sbi_call(SBI_CONSOLE_PUTCHAR, c, 0, 0);
+    if DBCN.available() {
+        sbi_call(SBI_EXT_DBCN, DBCN_CONSOLE_WRITE_BYTE, c & 0xff, 0, 0);
+    } else {
+        sbi_call_legacy(SBI_CONSOLE_PUTCHAR, c, 0, 0);
+    }
+}
+
+/// Write `bytes` to the console.
+///
+/// DBCN takes the whole buffer at once, by its physical address, which is
+/// the same as its virtual address in this kernel. It may write only a part,
+/// so the rest is sent again.
+pub fn console_write(bytes: &[u8]) {
+    if !DBCN.available() {
+        bytes.iter().for_each(|&b| console_putchar(b as usize));
+        return;
+    }
+    let mut rest = bytes;
+    while !rest.is_empty() {
+        let ret = sbi_call(
+            SBI_EXT_DBCN,
+            DBCN_CONSOLE_WRITE,
+            rest.len(),
+            rest.as_ptr() as usize,
+            0,
+        );
+        if !ret.is_ok() {
+            return;
+        }
+        rest = &rest[ret.value.min(rest.len())..];
+    }
+}
+
+/// use sbi call to getchar from console (qemu uart handler)
+///
+/// Returns `None` right away when no character is available.
+pub fn console_getchar() -> Option<u8> {
+    if DBCN.available() {
+        let mut c = 0u8;
+        let ret = sbi_call(
+            SBI_EXT_DBCN,
+            DBCN_CONSOLE_READ,
+            1,
+            &mut c as *mut u8 as usize,
+            0,
+        );
+        return (ret.is_ok() && ret.value == 1).then_some(c);
+    }
+    match sbi_call_legacy(SBI_CONSOLE_GETCHAR, 0, 0, 0) as isize {
+        -1 => None,
+        c => Some(c as u8),
+    }
+}
+
+/// Use sbi call to set the timer. A supervisor timer interrupt becomes
+/// pending once the `time` CSR reaches `timer`.
+pub fn set_timer(timer: usize) {
+    if TIME.available() {
+        sbi_call(SBI_EXT_TIME, TIME_SET_TIMER, timer, 0, 0);
+    } else {
+        sbi_call_legacy(SBI_SET_TIMER, timer, 0, 0);
+    }
 }
 
 /// Use sbi call to shutdown the kernel
-pub fn shutdown() -> ! {
-    sbi_call(SBI_SHUTDOWN, 0, 0, 0);
-    panic!("It should shotdown!");
+///
+/// `failure` is passed as the SRST reset reason, so the firmware can report it.
+/// If SRST is missing or returns, we use the legacy shutdown instead.
+///
+/// This is also called on the panic path, so it must not panic itself.
+pub fn shutdown(failure: bool) -> ! {
+    let reason = if failure {
+        SRST_REASON_FAILURE
+    } else {
+        SRST_REASON_NONE
+    };
+    if SRST.available() {
+        sbi_call(SBI_EXT_SRST, SRST_SYSTEM_RESET, SRST_TYPE_SHUTDOWN, reason, 0);
+    }
+    sbi_call_legacy(SBI_SHUTDOWN, 0, 0, 0);
+    loop {}
 }
@@ -0,0 +1,72 @@
+//! Multi-hart support
+//!
+//! The boot hart starts the other harts with SBI HSM; all of them then take
+//! tasks from the same task list, see `task::run_tasks`.
+//! Each hart keeps its id in `tp` while in the kernel (see `entry.asm` and `trap.S`).
+//!
+//! Shared kernel data is guarded by its own lock (see `sync`), but code paths
+//! such as the GDB stub and the console output still expect to be alone in
+//! the kernel. So the whole kernel is also one critical section: a hart
+//! takes the kernel lock when it enters the kernel, and releases it when it
+//! returns to user mode in `__restore`, or while it waits idle for a task.
+
+use crate::config::MAX_HARTS;
+use crate::sbi::{hart_get_status, hart_start, hsm_available};
+use core::arch::asm;
+use core::hint::spin_loop;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// The kernel lock, held by the hart running kernel code
+static KERNEL_LOCK: AtomicBool = AtomicBool::new(false);
+
+/// Id of the hart we are running on
+pub fn hart_id() -> usize {
+    let id;
+    unsafe {
+        asm!("mv {}, tp", out(reg) id);
+    }
+    id
+}
+
+/// Wait until no other hart runs kernel code, and take the kernel lock.
+pub fn lock_kernel() {
+    while KERNEL_LOCK
+        .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+        .is_err()
+    {
+        while KERNEL_LOCK.load(Ordering::Relaxed) {
+            spin_loop();
+        }
+    }
+}
+
+/// Release the kernel lock. Called by `__restore` on the way back to user mode.
+#[no_mangle]
+pub extern "C" fn unlock_kernel() {
+    KERNEL_LOCK.store(false, Ordering::Release);
+}
+
+/// Start every other hart at `_start_secondary`, which calls `rust_main_secondary`.
+///
+/// Without HSM, the kernel runs on the boot hart only.
+pub fn start_secondary_harts() {
+    if !hsm_available() {
+        return;
+    }
+    extern "C" {
+        fn _start_secondary();
+    }
+    let boot_hart = hart_id();
+    for id in (0..MAX_HARTS).filter(|&id| id != boot_hart) {
+        // an error means there is no such hart
+        if !hart_get_status(id).is_ok() {
+            continue;
+        }
+        let ret = hart_start(id, _start_secondary as usize, 0);
+        if ret.is_ok() {
+            info!("[kernel] starting hart {}", id);
+        } else {
+            warn!("[kernel] cannot start hart {}: SBI error {}", id, ret.error);
+        }
+    }
+}
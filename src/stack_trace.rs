@@ -0,0 +1,55 @@
+//! Frame-pointer based stack trace
+//!
+//! The kernel is built with `-Cforce-frame-pointers=yes`, so every function keeps
+//! its frame pointer in `fp` (`s0`). With the RISC-V frame layout,
+//! the return address is stored at `fp - 8` and the caller's `fp` at `fp - 16`.
+//!
+//! Each return address is printed with the function it belongs to, see `ksym`.
+//!
+//! The walk only follows frame pointers inside a known kernel stack and stops
+//! as soon as the chain leaves it, e.g., at the user registers saved by `__alltraps`.
+
+use crate::config::BOOT_STACK_SIZE;
+use crate::ksym::Symbolized;
+use crate::smp::hart_id;
+use core::arch::asm;
+
+/// Upper bound of frames printed, in case the chain is corrupted
+const MAX_DEPTH: usize = 64;
+
+/// The kernel stack containing `fp`, as `[bottom, top)`:
+/// the boot stack of this hart, or the kernel stack of an app
+fn stack_of(fp: usize) -> Option<(usize, usize)> {
+    extern "C" {
+        fn boot_stack_top(); // stack top of hart 0
+    }
+    let top = boot_stack_top as usize - hart_id() * BOOT_STACK_SIZE;
+    if fp > top - BOOT_STACK_SIZE && fp <= top {
+        return Some((top - BOOT_STACK_SIZE, top));
+    }
+    crate::batch::kernel_stack_containing(fp)
+}
+
+/// Print the return addresses of the current call chain, innermost first
+pub fn print_stack_trace() {
+    let mut fp: usize;
+    unsafe {
+        asm!("mv {}, fp", out(reg) fp);
+    }
+    println!("[kernel] stack trace:");
+    for depth in 0..MAX_DEPTH {
+        let (bottom, top) = match stack_of(fp) {
+            // both saved words must lie inside the same stack
+            Some((bottom, top)) if fp % 8 == 0 && fp - 16 >= bottom => (bottom, top),
+            _ => break,
+        };
+        let ra = unsafe { ((fp - 8) as *const usize).read_volatile() };
+        let prev_fp = unsafe { ((fp - 16) as *const usize).read_volatile() };
+        println!("[kernel]   #{:<2} {}", depth, Symbolized(ra));
+        // the caller's frame is always above ours; anything else means a broken chain
+        if prev_fp <= fp || prev_fp > top || prev_fp <= bottom {
+            break;
+        }
+        fp = prev_fp;
+    }
+}
@@ -0,0 +1,68 @@
+//! A lock for data shared with trap handlers
+
+use super::{SpinMutex, SpinMutexGuard};
+use core::ops::{Deref, DerefMut};
+use riscv::register::sstatus;
+
+/// A `SpinMutex` that also disables interrupts on this hart (`sstatus.SIE`)
+/// while it is held.
+///
+/// Otherwise an interrupt handler wanting the same lock would spin forever
+/// on a lock its own hart holds. The previous state of `SIE` is restored
+/// when the guard is dropped, so these locks nest.
+pub struct IrqSafeLock<T> {
+    inner: SpinMutex<T>,
+}
+
+/// Access to the data of a locked `IrqSafeLock`
+pub struct IrqSafeLockGuard<'a, T> {
+    /// dropped before `SIE` is restored
+    guard: Option<SpinMutexGuard<'a, T>>,
+    /// whether `SIE` was set before the lock was taken
+    sie: bool,
+}
+
+impl<T> IrqSafeLock<T> {
+    /// A new, unlocked lock
+    pub const fn new(value: T) -> Self {
+        Self {
+            inner: SpinMutex::new(value),
+        }
+    }
+
+    /// Disable interrupts, then spin until the lock is free and take it.
+    pub fn lock(&self) -> IrqSafeLockGuard<'_, T> {
+        let sie = sstatus::read().sie();
+        unsafe {
+            sstatus::clear_sie();
+        }
+        IrqSafeLockGuard {
+            guard: Some(self.inner.lock()),
+            sie,
+        }
+    }
+}
+
+impl<T> Deref for IrqSafeLockGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        self.guard.as_ref().unwrap()
+    }
+}
+
+impl<T> DerefMut for IrqSafeLockGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.guard.as_mut().unwrap()
+    }
+}
+
+impl<T> Drop for IrqSafeLockGuard<'_, T> {
+    fn drop(&mut self) {
+        self.guard = None;
+        if self.sie {
+            unsafe {
+                sstatus::set_sie();
+            }
+        }
+    }
+}
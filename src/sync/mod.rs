@@ -1,7 +1,15 @@
 //! Synchronization and interior mutability primitives
-//! 
-//! Re-export the struct `UPSafeCell`.
+//!
+//! - `UPSafeCell`: a `RefCell` for data only one hart ever touches, e.g. per-hart state.
+//!   A double borrow reports where the data is already borrowed.
+//! - `SpinMutex` and `TicketLock`: spin locks for data shared between harts.
+//! - `IrqSafeLock`: a spin lock that also disables interrupts while held,
+//!   for data shared with trap handlers.
 
+mod irq;
+mod spin;
 mod up;
 
-pub use up::UPSafeCell;
+pub use irq::{IrqSafeLock, IrqSafeLockGuard};
+pub use spin::{SpinMutex, SpinMutexGuard, TicketLock, TicketLockGuard};
+pub use up::{BorrowConflict, UPSafeCell, UPSafeCellGuard};
@@ -0,0 +1,132 @@
+//! Spinning mutual exclusion: a test-and-set `SpinMutex` and a fair `TicketLock`
+//!
+//! Both hand out RAII guards, so the lock is released when the guard is dropped.
+//! Neither touches interrupts; see `IrqSafeLock` for data also used in trap handlers.
+
+use core::cell::UnsafeCell;
+use core::hint::spin_loop;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+/// A test-and-set spin lock around a `T`
+pub struct SpinMutex<T> {
+    locked: AtomicBool,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Sync for SpinMutex<T> {}
+unsafe impl<T: Send> Send for SpinMutex<T> {}
+
+/// Access to the data of a locked `SpinMutex`; unlocks it when dropped.
+pub struct SpinMutexGuard<'a, T> {
+    lock: &'a SpinMutex<T>,
+}
+
+impl<T> SpinMutex<T> {
+    /// A new, unlocked mutex
+    pub const fn new(value: T) -> Self {
+        Self {
+            locked: AtomicBool::new(false),
+            data: UnsafeCell::new(value),
+        }
+    }
+
+    /// Spin until the lock is free, then take it.
+    pub fn lock(&self) -> SpinMutexGuard<'_, T> {
+        loop {
+            if let Some(guard) = self.try_lock() {
+                return guard;
+            }
+            // wait without writing, so the cache line is not bounced between harts
+            while self.locked.load(Ordering::Relaxed) {
+                spin_loop();
+            }
+        }
+    }
+
+    /// Take the lock if it is free.
+    pub fn try_lock(&self) -> Option<SpinMutexGuard<'_, T>> {
+        self.locked
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .ok()
+            .map(|_| SpinMutexGuard { lock: self })
+    }
+}
+
+impl<T> Deref for SpinMutexGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T> DerefMut for SpinMutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<T> Drop for SpinMutexGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.locked.store(false, Ordering::Release);
+    }
+}
+
+/// A ticket lock around a `T`: harts get the lock in the order they asked for it,
+/// so none of them starves.
+pub struct TicketLock<T> {
+    /// the ticket handed to the next hart asking for the lock
+    next_ticket: AtomicUsize,
+    /// the ticket of the holder
+    now_serving: AtomicUsize,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Sync for TicketLock<T> {}
+unsafe impl<T: Send> Send for TicketLock<T> {}
+
+/// Access to the data of a locked `TicketLock`; serves the next ticket when dropped.
+pub struct TicketLockGuard<'a, T> {
+    lock: &'a TicketLock<T>,
+}
+
+impl<T> TicketLock<T> {
+    /// A new, unlocked ticket lock
+    pub const fn new(value: T) -> Self {
+        Self {
+            next_ticket: AtomicUsize::new(0),
+            now_serving: AtomicUsize::new(0),
+            data: UnsafeCell::new(value),
+        }
+    }
+
+    /// Take a ticket and spin until it is served.
+    pub fn lock(&self) -> TicketLockGuard<'_, T> {
+        let ticket = self.next_ticket.fetch_add(1, Ordering::Relaxed);
+        while self.now_serving.load(Ordering::Acquire) != ticket {
+            spin_loop();
+        }
+        TicketLockGuard { lock: self }
+    }
+}
+
+impl<T> Deref for TicketLockGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T> DerefMut for TicketLockGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<T> Drop for TicketLockGuard<'_, T> {
+    fn drop(&mut self) {
+        // only the holder writes `now_serving`
+        let next = self.lock.now_serving.load(Ordering::Relaxed) + 1;
+        self.lock.now_serving.store(next, Ordering::Release);
+    }
+}
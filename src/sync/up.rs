@@ -1,32 +1,109 @@
 //! Uniprocessor interior mutability primitives
 
-use core::cell::{RefCell, RefMut};
+use core::cell::{Cell, RefCell, RefMut};
+use core::fmt;
+use core::ops::{Deref, DerefMut};
+use core::panic::Location;
 
-/// Wrap a static data structure inside it so that 
+/// Wrap a static data structure inside it so that
 /// we are able to access it without any `unsafe`.
-/// 
+///
 /// We should only use it in uniprocessor.
-/// 
+///
 /// In order to get mutable reference of inner data,
 /// call `exclusive_access`.
+///
+/// The cell remembers where the current borrow was taken, so a second borrow,
+/// e.g. from a trap handler reentering the same code, reports both places.
 pub struct UPSafeCell<T> {
     /// inner data
     inner: RefCell<T>,
+    /// caller of `exclusive_access` holding the current borrow
+    holder: Cell<Option<&'static Location<'static>>>,
 }
 
 unsafe impl<T> Sync for UPSafeCell<T> {}
 
+/// A conflicting borrow of a `UPSafeCell`
+#[derive(Debug, Clone, Copy)]
+pub struct BorrowConflict {
+    /// where the current borrow was taken, if known
+    pub holder: Option<&'static Location<'static>>,
+    /// where the conflicting borrow was requested
+    pub caller: &'static Location<'static>,
+}
+
+impl fmt::Display for BorrowConflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "UPSafeCell borrowed at {} ", self.caller)?;
+        match self.holder {
+            Some(holder) => write!(f, "while already borrowed at {}", holder),
+            None => write!(f, "while already borrowed"),
+        }
+    }
+}
+
+/// Mutable access to the data of a `UPSafeCell`; the borrow ends when it is dropped.
+pub struct UPSafeCellGuard<'a, T> {
+    inner: RefMut<'a, T>,
+    holder: &'a Cell<Option<&'static Location<'static>>>,
+}
+
 impl<T> UPSafeCell<T> {
     /// User is responsible to guarantee that inner struct
     /// is only used in uniprocessor.
     pub unsafe fn new(value: T) -> Self {
         Self {
             inner: RefCell::new(value),
+            holder: Cell::new(None),
+        }
+    }
+
+    /// It will panic if the data has already been borrowed,
+    /// naming both the holder and the caller.
+    #[track_caller]
+    pub fn exclusive_access(&self) -> UPSafeCellGuard<'_, T> {
+        match self.try_exclusive_access() {
+            Ok(guard) => guard,
+            Err(conflict) => panic!("{}", conflict),
         }
     }
 
-    /// It will panic if the data has already been borrowed.
-    pub fn exclusive_access(&self) -> RefMut<'_, T> {
-        self.inner.borrow_mut()
+    /// Like `exclusive_access`, but returns the conflict instead of panicking.
+    #[track_caller]
+    pub fn try_exclusive_access(&self) -> Result<UPSafeCellGuard<'_, T>, BorrowConflict> {
+        let caller = Location::caller();
+        match self.inner.try_borrow_mut() {
+            Ok(inner) => {
+                self.holder.set(Some(caller));
+                Ok(UPSafeCellGuard {
+                    inner,
+                    holder: &self.holder,
+                })
+            }
+            Err(_) => Err(BorrowConflict {
+                holder: self.holder.get(),
+                caller,
+            }),
+        }
+    }
+}
+
+impl<T> Deref for UPSafeCellGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        &self.inner
     }
-}
\ No newline at end of file
+}
+
+impl<T> DerefMut for UPSafeCellGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+}
+
+impl<T> Drop for UPSafeCellGuard<'_, T> {
+    fn drop(&mut self) {
+        self.holder.set(None);
+    }
+}
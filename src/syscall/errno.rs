@@ -0,0 +1,12 @@
+//! Linux error numbers returned by syscalls
+//!
+//! A failing syscall returns the negated error number, e.g. `-EINVAL`.
+
+/// Bad file number
+pub const EBADF: isize = 9;
+/// Bad address
+pub const EFAULT: isize = 14;
+/// Invalid argument
+pub const EINVAL: isize = 22;
+/// Function not implemented
+pub const ENOSYS: isize = 38;
@@ -1,19 +1,75 @@
 //! File and filesystem-related syscalls
+use super::errno::{EBADF, EFAULT};
+use crate::config::STDIN_POLL_INTERVAL_MS;
+use crate::console::{read_byte, write_bytes};
+use crate::mm::{user_slice, user_slice_mut};
+use crate::task::sleep_current_and_run_next;
+use crate::timer::{get_time, sec_ns_to_ticks};
 
+const FD_STDIN: usize = 0;
 const FD_STDOUT: usize = 1;
 
+/// read up to `len` bytes from a file with `fd` into `buf`
+///
+/// Only stdin is supported. The call blocks until at least one byte has been
+/// typed, sleeping between checks of the console so other apps can run, then
+/// returns what is available without waiting for more.
+/// Returns `-EFAULT` if the buffer is not in the memory of the app,
+/// and `-EBADF` if `fd` is not a file we can read from.
+pub fn sys_read(fd: usize, buf: *mut u8, len: usize) -> isize {
+    trace!("kernel: sys_read");
+    match fd {
+        FD_STDIN => {
+            let slice = match user_slice_mut(buf, len) {
+                Some(slice) => slice,
+                None => return -EFAULT,
+            };
+            if slice.is_empty() {
+                return 0;
+            }
+            slice[0] = loop {
+                if let Some(c) = read_byte() {
+                    break c;
+                }
+                let interval = sec_ns_to_ticks(0, STDIN_POLL_INTERVAL_MS * 1_000_000);
+                sleep_current_and_run_next(get_time() + interval);
+            };
+            let mut count = 1;
+            while count < slice.len() {
+                match read_byte() {
+                    Some(c) => slice[count] = c,
+                    None => break,
+                }
+                count += 1;
+            }
+            count as isize
+        }
+        _ => {
+            warn!("kernel: sys_read from unsupported fd {}", fd);
+            -EBADF
+        }
+    }
+}
+
 /// write buf of length `len`  to a file with `fd`
+///
+/// The bytes are passed to the console as they are, UTF-8 or not.
+/// Returns `-EFAULT` if the buffer is not in the memory of the app,
+/// and `-EBADF` if `fd` is not a file we can write to.
 pub fn sys_write(fd: usize, buf: *const u8, len: usize) -> isize {
     trace!("kernel: sys_write");
     match fd {
         FD_STDOUT => {
-            let slice = unsafe { core::slice::from_raw_parts(buf, len) };
-            let str = core::str::from_utf8(slice).unwrap();
-            print!("{}", str);
+            let slice = match user_slice(buf, len) {
+                Some(slice) => slice,
+                None => return -EFAULT,
+            };
+            write_bytes(slice);
             len as isize
         }
         _ => {
-            panic!("Unsupported fd in sys_write!");
+            warn!("kernel: sys_write to unsupported fd {}", fd);
+            -EBADF
         }
     }
 }
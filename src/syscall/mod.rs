@@ -2,22 +2,90 @@
 //! 
 //! The single entry point to all system calls.
 
+/// read syscall
+const SYSCALL_READ: usize = 63;
 /// write syscall
 const SYSCALL_WRITE: usize = 64;
 /// exit syscall
 const SYSCALL_EXIT: usize = 93;
+/// nanosleep syscall
+const SYSCALL_NANOSLEEP: usize = 101;
+/// clock_gettime syscall
+const SYSCALL_CLOCK_GETTIME: usize = 113;
+/// yield syscall
+const SYSCALL_YIELD: usize = 124;
+/// gettimeofday syscall
+const SYSCALL_GET_TIME: usize = 169;
 
+mod errno;
 mod fs;
 mod process;
 
+use crate::batch::get_app_name;
+use crate::config::env_choice;
+use crate::report::Outcome;
+use crate::task::{current_task_id, exit_current_and_run_next};
+use errno::ENOSYS;
 use fs::*;
 use process::*;
 
+/// What to do when an app makes a syscall we do not support.
+///
+/// Selected at build time with the `UNKNOWN_SYSCALL_POLICY` environment variable.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum UnknownSyscallPolicy {
+    /// Report it and return `-ENOSYS` (`UNKNOWN_SYSCALL_POLICY=ENOSYS`, the default)
+    Enosys,
+    /// Report it and kill the app (`UNKNOWN_SYSCALL_POLICY=KILL`)
+    Kill,
+}
+
+fn unknown_syscall_policy() -> UnknownSyscallPolicy {
+    env_choice(
+        option_env!("UNKNOWN_SYSCALL_POLICY"),
+        &[
+            ("ENOSYS", UnknownSyscallPolicy::Enosys),
+            ("KILL", UnknownSyscallPolicy::Kill),
+        ],
+        UnknownSyscallPolicy::Enosys,
+    )
+}
+
+/// Report a syscall we do not support, then either return `-ENOSYS`
+/// or kill the app, depending on `unknown_syscall_policy()`.
+fn sys_unknown(syscall_id: usize, args: [usize; 3], sepc: usize) -> isize {
+    let app_id = current_task_id();
+    println!(
+        "[kernel] app_{} ({}) made unsupported syscall {} at sepc = {:#x}, args = [{:#x}, {:#x}, {:#x}]",
+        app_id,
+        get_app_name(app_id),
+        syscall_id,
+        sepc,
+        args[0],
+        args[1],
+        args[2]
+    );
+    match unknown_syscall_policy() {
+        UnknownSyscallPolicy::Enosys => -ENOSYS,
+        UnknownSyscallPolicy::Kill => {
+            println!("[kernel] Unsupported syscall in application, kernel killed it.");
+            exit_current_and_run_next(Outcome::BadSyscall { syscall_id, sepc })
+        }
+    }
+}
+
 /// handle syscall exception with `syscall_id` and other arguments
-pub fn syscall(syscall_id: usize, args: [usize; 3]) -> isize {
+///
+/// `sepc` is the address of the `ecall`, taken from the `TrapContext`, for reports.
+pub fn syscall(syscall_id: usize, args: [usize; 3], sepc: usize) -> isize {
     match syscall_id {
+        SYSCALL_READ => sys_read(args[0], args[1] as *mut u8, args[2]),
         SYSCALL_WRITE => sys_write(args[0], args[1] as *const u8, args[2]),
         SYSCALL_EXIT => sys_exit(args[0] as i32),
-        _ => panic!("Unsupported syscall_id: {}", syscall_id),
+        SYSCALL_YIELD => sys_yield(),
+        SYSCALL_NANOSLEEP => sys_nanosleep(args[0] as *const TimeSpec, args[1] as *mut TimeSpec),
+        SYSCALL_CLOCK_GETTIME => sys_clock_gettime(args[0], args[1] as *mut TimeSpec),
+        SYSCALL_GET_TIME => sys_get_time(args[0] as *mut TimeVal, args[1]),
+        _ => sys_unknown(syscall_id, args, sepc),
     }
 }
@@ -1,8 +1,103 @@
 //! App management syscalls
-use crate::batch::run_next_app;
+use super::errno::{EFAULT, EINVAL};
+use crate::mm::{read_user, write_user};
+use crate::report::Outcome;
+use crate::task::{
+    exit_current_and_run_next, sleep_current_and_run_next, suspend_current_and_run_next,
+};
+use crate::timer::{get_time, sec_ns_to_ticks, ticks_to_sec_ns};
+
+/// `clock_gettime` clock: wall-clock time
+const CLOCK_REALTIME: usize = 0;
+/// `clock_gettime` clock: time since an unspecified point
+const CLOCK_MONOTONIC: usize = 1;
+
+/// Time as returned by `gettimeofday`
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct TimeVal {
+    /// seconds
+    pub sec: usize,
+    /// microseconds
+    pub usec: usize,
+}
+
+/// Time as returned by `clock_gettime`
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct TimeSpec {
+    /// seconds
+    pub sec: usize,
+    /// nanoseconds
+    pub nsec: usize,
+}
 
 /// task exits and submit an exit code
 pub fn sys_exit(exit_code: i32) -> ! {
     trace!("[kernel] Application exited with code {}", exit_code);
-    run_next_app()
+    exit_current_and_run_next(Outcome::Exited(exit_code))
+}
+
+/// current task gives up resources for other tasks
+pub fn sys_yield() -> isize {
+    trace!("kernel: sys_yield");
+    suspend_current_and_run_next();
+    0
+}
+
+/// sleep for the duration in `req`, letting other tasks run meanwhile.
+///
+/// The sleep is never interrupted, so `rem`, if not null, is set to zero.
+pub fn sys_nanosleep(req: *const TimeSpec, rem: *mut TimeSpec) -> isize {
+    trace!("kernel: sys_nanosleep");
+    let TimeSpec { sec, nsec } = match read_user(req) {
+        Some(ts) => ts,
+        None => return -EFAULT,
+    };
+    if nsec >= 1_000_000_000 {
+        return -EINVAL;
+    }
+    // check `rem` before sleeping, so a bad pointer is not found too late
+    if !rem.is_null() && read_user(rem).is_none() {
+        return -EFAULT;
+    }
+    sleep_current_and_run_next(get_time().saturating_add(sec_ns_to_ticks(sec, nsec)));
+    if !rem.is_null() {
+        write_user(rem, TimeSpec { sec: 0, nsec: 0 });
+    }
+    0
+}
+
+/// get the time since boot into `ts`, with the `gettimeofday` ABI.
+///
+/// There is no real-time clock, so the wall-clock time starts at boot.
+/// The time zone `_tz` is ignored.
+pub fn sys_get_time(ts: *mut TimeVal, _tz: usize) -> isize {
+    trace!("kernel: sys_get_time");
+    let (sec, nsec) = ticks_to_sec_ns(get_time());
+    match write_user(
+        ts,
+        TimeVal {
+            sec,
+            usec: nsec / 1000,
+        },
+    ) {
+        Some(()) => 0,
+        None => -EFAULT,
+    }
+}
+
+/// get the time of `clock_id` since boot into `tp`, with the `clock_gettime` ABI.
+///
+/// Both `CLOCK_REALTIME` and `CLOCK_MONOTONIC` count from boot.
+pub fn sys_clock_gettime(clock_id: usize, tp: *mut TimeSpec) -> isize {
+    trace!("kernel: sys_clock_gettime");
+    if clock_id != CLOCK_REALTIME && clock_id != CLOCK_MONOTONIC {
+        return -EINVAL;
+    }
+    let (sec, nsec) = ticks_to_sec_ns(get_time());
+    match write_user(tp, TimeSpec { sec, nsec }) {
+        Some(()) => 0,
+        None => -EFAULT,
+    }
 }
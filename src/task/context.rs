@@ -0,0 +1,41 @@
+//! Implement the struct `TaskContext`
+
+#[derive(Copy, Clone)]
+#[repr(C)]
+/// Task Context.
+///
+/// The registers `__switch` saves when a task gives up the CPU, and restores
+/// when it gets the CPU back. Caller-saved registers are already on the
+/// kernel stack of the task by then, so only `ra`, `sp` and `s0`-`s11` are kept.
+pub struct TaskContext {
+    /// return address (e.g. `__restore`) of `__switch`
+    ra: usize,
+    /// kernel stack pointer of the task
+    sp: usize,
+    /// callee saved registers: s0..s11
+    s: [usize; 12],
+}
+
+impl TaskContext {
+    /// An empty context, for tasks that never ran
+    pub fn zero_init() -> Self {
+        Self {
+            ra: 0,
+            sp: 0,
+            s: [0; 12],
+        }
+    }
+
+    /// A context whose first `__switch` returns to `__restore`, with `sp` at
+    /// the `TrapContext` pushed on the kernel stack, so the task starts in user mode.
+    pub fn goto_restore(kstack_ptr: usize) -> Self {
+        extern "C" {
+            fn __restore();
+        }
+        Self {
+            ra: __restore as usize,
+            sp: kstack_ptr,
+            s: [0; 12],
+        }
+    }
+}
@@ -0,0 +1,261 @@
+//! Task management implementation
+//!
+//! Everything about task management, like starting and switching tasks is
+//! implemented here.
+//!
+//! A single global instance of `TaskManager` called `TASK_MANAGER` controls
+//! all the tasks in the batch. Every app is a task: all of them are loaded
+//! into their own slot up front, and they take turns on the harts.
+//! What each hart runs is kept in `processor`.
+//!
+//! Be careful when you see `__switch` ASM function in `switch.S`. Control flow around this function
+//! might not be what you expect.
+
+mod context;
+mod processor;
+mod switch;
+#[allow(clippy::module_inception)]
+mod task;
+
+use crate::batch::{get_app_name, get_app_time_limit_ms, get_num_app, init_app_cx, load_app};
+use crate::config::MAX_APP_NUM;
+use crate::qemu;
+use crate::report::{self, Outcome};
+use crate::sync::TicketLock;
+use crate::timer::{add_timer, get_time, get_time_us, pop_expired_timer, ticks_to_us};
+use lazy_static::*;
+use processor::{current_task, schedule};
+pub use processor::{current_slice_end, run_tasks};
+pub use task::{TaskControlBlock, TaskStatus};
+
+pub use context::TaskContext;
+
+/// The task manager, where all the tasks are managed.
+///
+/// Functions implemented on `TaskManager` deals with all task state transitions
+/// and task context switching. For convenience, you can find wrappers around it
+/// in the module level.
+///
+/// Most of `TaskManager` are hidden behind the field `inner`, to defer
+/// borrowing checks to runtime. You can see examples on how to use `inner` in
+/// existing functions on `TaskManager`.
+pub struct TaskManager {
+    /// total number of tasks
+    num_app: usize,
+    /// use inner value to get mutable access.
+    /// No other lock is taken while it is held, e.g., `APP_MANAGER` or `REPORT`.
+    inner: TicketLock<TaskManagerInner>,
+}
+
+/// Inner of Task Manager
+pub struct TaskManagerInner {
+    /// task list
+    tasks: [TaskControlBlock; MAX_APP_NUM],
+    /// where the round-robin search for a `Ready` task starts
+    next_task: usize,
+}
+
+lazy_static! {
+    /// Global variable: TASK_MANAGER
+    ///
+    /// Every app is loaded into its slot here. An app that cannot be loaded
+    /// is recorded in the report and never becomes `Ready`.
+    pub static ref TASK_MANAGER: TaskManager = {
+        let num_app = get_num_app();
+        let mut tasks = [TaskControlBlock {
+            task_cx: TaskContext::zero_init(),
+            task_status: TaskStatus::UnInit,
+            start_time_us: None,
+            cpu_time: 0,
+            run_start: 0,
+            time_limit_ms: 0,
+        }; MAX_APP_NUM];
+        for (i, task) in tasks.iter_mut().enumerate().take(num_app) {
+            task.time_limit_ms = get_app_time_limit_ms(i);
+            match load_app(i) {
+                Ok(entry) => {
+                    task.task_cx = TaskContext::goto_restore(init_app_cx(i, entry));
+                    task.task_status = TaskStatus::Ready;
+                }
+                Err(_) => {
+                    task.task_status = TaskStatus::Exited;
+                    report::record(i, get_app_name(i), Outcome::LoadFailed, 0);
+                }
+            }
+        }
+        TaskManager {
+            num_app,
+            inner: TicketLock::new(TaskManagerInner {
+                tasks,
+                next_task: 0,
+            }),
+        }
+    };
+}
+
+impl TaskManager {
+    /// Find the next `Ready` task in round-robin order, and mark it `Running`
+    /// on the calling hart.
+    fn fetch_ready_task(&self) -> Option<usize> {
+        let mut inner = self.inner.lock();
+        let from = inner.next_task;
+        let next = (from..from + self.num_app)
+            .map(|id| id % self.num_app)
+            .find(|id| inner.tasks[*id].task_status == TaskStatus::Ready)?;
+        inner.next_task = next + 1;
+        let task = &mut inner.tasks[next];
+        task.task_status = TaskStatus::Running;
+        task.start_time_us.get_or_insert_with(get_time_us);
+        task.run_start = get_time();
+        Some(next)
+    }
+
+    /// Where the context of task `id` is saved while it is switched out
+    ///
+    /// The pointer outlives the `inner` guard: `__switch` reads and writes
+    /// through it without the ticket lock. This is only sound because the
+    /// caller holds the kernel lock (see `smp`) across the switch, so no
+    /// other hart can switch the same task in or out meanwhile.
+    fn task_cx_ptr(&self, id: usize) -> *mut TaskContext {
+        &mut self.inner.lock().tasks[id].task_cx as *mut TaskContext
+    }
+
+    /// Print the summary and exit if every task has exited.
+    fn finish_batch_if_done(&self) {
+        let inner = self.inner.lock();
+        let done = inner.tasks[..self.num_app]
+            .iter()
+            .all(|task| task.task_status == TaskStatus::Exited);
+        drop(inner);
+        if done {
+            finish_batch();
+        }
+    }
+
+    /// Change the status of `Running` task `id` into `status`,
+    /// adding the time of this run to its CPU time.
+    fn stop_running(&self, id: usize, status: TaskStatus) {
+        let mut inner = self.inner.lock();
+        let task = &mut inner.tasks[id];
+        task.cpu_time += get_time() - task.run_start;
+        task.task_status = status;
+    }
+
+    /// Change the status of every task whose deadline has passed into `Ready`.
+    fn wake_expired_tasks(&self) {
+        while let Some(id) = pop_expired_timer() {
+            let mut inner = self.inner.lock();
+            if inner.tasks[id].task_status == TaskStatus::Sleeping {
+                inner.tasks[id].task_status = TaskStatus::Ready;
+            }
+        }
+    }
+
+    /// The time limit of task `id` in milliseconds,
+    /// if its CPU time has gone beyond it
+    fn time_limit_exceeded(&self, id: usize) -> Option<usize> {
+        let inner = self.inner.lock();
+        let task = &inner.tasks[id];
+        let cpu_time = task.cpu_time + get_time() - task.run_start;
+        if ticks_to_us(cpu_time) > task.time_limit_ms * 1000 {
+            Some(task.time_limit_ms)
+        } else {
+            None
+        }
+    }
+
+    /// Do not count the last `ticks` in the CPU time of `Running` task `id`.
+    ///
+    /// Never moves `run_start` past now, so time excluded twice,
+    /// e.g., a wait for the kernel lock during a GDB session, is harmless.
+    fn exclude_cpu_time(&self, id: usize, ticks: usize) {
+        let mut inner = self.inner.lock();
+        let task = &mut inner.tasks[id];
+        task.run_start = (task.run_start + ticks).min(get_time());
+    }
+
+    /// `exclude_cpu_time` for every `Running` task
+    fn exclude_running_cpu_time(&self, ticks: usize) {
+        for id in 0..self.num_app {
+            if self.inner.lock().tasks[id].task_status == TaskStatus::Running {
+                self.exclude_cpu_time(id, ticks);
+            }
+        }
+    }
+
+    /// Record how task `id` left the batch in the report
+    fn record(&self, id: usize, outcome: Outcome) {
+        // `get_app_name` and `report::record` take their own locks
+        let start = self.inner.lock().tasks[id].start_time_us;
+        let start = start.unwrap_or_else(get_time_us);
+        report::record(id, get_app_name(id), outcome, get_time_us() - start);
+    }
+}
+
+/// Print the batch summary and exit QEMU with the batch status
+fn finish_batch() -> ! {
+    println!("All applications completed!");
+    report::print_summary();
+    println!("[kernel] heap: {}", crate::mm::heap_stats());
+    qemu::exit(report::exit_status())
+}
+
+/// Get the id of the `Running` task on this hart, which is also its app id
+pub fn current_task_id() -> usize {
+    current_task().expect("no task is running on this hart")
+}
+
+/// The time limit of the current task in milliseconds,
+/// if its CPU time has gone beyond it.
+///
+/// Checked on every timer interrupt, so a task is killed at most
+/// one time slice after its limit.
+pub fn current_time_limit_exceeded() -> Option<usize> {
+    TASK_MANAGER.time_limit_exceeded(current_task_id())
+}
+
+/// Do not count the last `ticks` in the CPU time of the current task,
+/// e.g., the time it waited for the kernel lock.
+pub fn exclude_current_cpu_time(ticks: usize) {
+    TASK_MANAGER.exclude_cpu_time(current_task_id(), ticks);
+}
+
+/// Do not count the last `ticks` in the CPU time of any running task,
+/// e.g., the time the kernel was stopped in the debugger.
+pub fn exclude_running_cpu_time(ticks: usize) {
+    TASK_MANAGER.exclude_running_cpu_time(ticks);
+}
+
+/// Wake up the sleeping tasks whose deadline has passed.
+pub fn wake_expired_tasks() {
+    TASK_MANAGER.wake_expired_tasks();
+}
+
+/// Put the current `Running` task to sleep until the `time` CSR reaches `deadline`,
+/// and run the next task. Returns once the task has been woken up and scheduled again.
+pub fn sleep_current_and_run_next(deadline: usize) {
+    let id = current_task_id();
+    TASK_MANAGER.stop_running(id, TaskStatus::Sleeping);
+    add_timer(deadline, id);
+    schedule(TASK_MANAGER.task_cx_ptr(id));
+}
+
+/// Suspend the current `Running` task and run the next task in task list.
+///
+/// If no other task is `Ready`, the current task is picked again.
+pub fn suspend_current_and_run_next() {
+    let id = current_task_id();
+    TASK_MANAGER.stop_running(id, TaskStatus::Ready);
+    schedule(TASK_MANAGER.task_cx_ptr(id));
+}
+
+/// Record how the current task left the batch, change its status into `Exited`
+/// and run the next task.
+pub fn exit_current_and_run_next(outcome: Outcome) -> ! {
+    let id = current_task_id();
+    crate::gdb::report_exit(id, &outcome);
+    TASK_MANAGER.record(id, outcome);
+    TASK_MANAGER.stop_running(id, TaskStatus::Exited);
+    schedule(TASK_MANAGER.task_cx_ptr(id));
+    panic!("Unreachable in task::exit_current_and_run_next!");
+}
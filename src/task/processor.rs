@@ -0,0 +1,104 @@
+//! What each hart is running
+//!
+//! Every hart has its own `Processor`, found through its hart id in `tp`.
+//! A hart runs the idle loop `run_tasks` on its boot stack: it picks a ready
+//! task and switches to it, and the task switches back to the idle loop when
+//! it gives up the CPU. A task is thus never switched out and in on two harts
+//! at the same time, and a hart waiting for work does not sit on the kernel
+//! stack of a task another hart may pick up.
+
+use super::switch::__switch;
+use super::{TaskContext, TASK_MANAGER};
+use crate::config::MAX_HARTS;
+use crate::smp::{hart_id, lock_kernel, unlock_kernel};
+use crate::sync::UPSafeCell;
+use crate::timer::set_next_trigger;
+use lazy_static::*;
+
+/// The state of one hart
+pub struct Processor {
+    /// id of the task running on this hart
+    current: Option<usize>,
+    /// where the idle loop was switched out
+    idle_task_cx: TaskContext,
+    /// when the time slice of the current task ends, in ticks
+    slice_end: usize,
+}
+
+lazy_static! {
+    /// One `Processor` per hart. Each hart only touches its own.
+    static ref PROCESSORS: [UPSafeCell<Processor>; MAX_HARTS] = core::array::from_fn(|_| unsafe {
+        UPSafeCell::new(Processor {
+            current: None,
+            idle_task_cx: TaskContext::zero_init(),
+            slice_end: 0,
+        })
+    });
+}
+
+/// The `Processor` of this hart
+fn this_processor() -> &'static UPSafeCell<Processor> {
+    &PROCESSORS[hart_id()]
+}
+
+/// Id of the task running on this hart, if any
+pub fn current_task() -> Option<usize> {
+    this_processor().exclusive_access().current
+}
+
+/// When the time slice of the task running on this hart ends, in ticks
+pub fn current_slice_end() -> usize {
+    this_processor().exclusive_access().slice_end
+}
+
+/// The idle loop of a hart: run ready tasks until the batch is over.
+///
+/// Called with the kernel lock held. The lock is released in `__restore`
+/// when a task is switched in, and while the hart waits for a task.
+pub fn run_tasks() -> ! {
+    loop {
+        if let Some(next) = TASK_MANAGER.fetch_ready_task() {
+            // give the task a full time slice
+            let slice_end = set_next_trigger();
+            let mut processor = this_processor().exclusive_access();
+            processor.current = Some(next);
+            processor.slice_end = slice_end;
+            let idle_task_cx_ptr = &mut processor.idle_task_cx as *mut TaskContext;
+            drop(processor);
+            unsafe {
+                __switch(idle_task_cx_ptr, TASK_MANAGER.task_cx_ptr(next));
+            }
+            // back in the idle loop once the task gave up the CPU
+            this_processor().exclusive_access().current = None;
+        } else {
+            TASK_MANAGER.finish_batch_if_done();
+            wait_for_task();
+        }
+    }
+}
+
+/// Wait with `wfi` until the next deadline of a sleeping task or the end of
+/// a time slice, when another hart may have left a task ready.
+///
+/// Interrupts stay disabled in the kernel, but `wfi` still returns once
+/// the timer interrupt is pending.
+fn wait_for_task() {
+    set_next_trigger();
+    unlock_kernel();
+    unsafe {
+        core::arch::asm!("wfi");
+    }
+    lock_kernel();
+    TASK_MANAGER.wake_expired_tasks();
+}
+
+/// Switch from the current task, whose context is saved into
+/// `switched_task_cx_ptr`, back to the idle loop of this hart.
+pub fn schedule(switched_task_cx_ptr: *mut TaskContext) {
+    let processor = this_processor().exclusive_access();
+    let idle_task_cx_ptr = &processor.idle_task_cx as *const TaskContext;
+    drop(processor);
+    unsafe {
+        __switch(switched_task_cx_ptr, idle_task_cx_ptr);
+    }
+}
@@ -0,0 +1,16 @@
+//! Rust wrapper around `__switch`.
+//!
+//! Switching to a different task's context happens here. The actual
+//! implementation must not be in Rust and (essentially) has to be in assembly
+//! language, since it swaps the stack pointer and the callee-saved registers.
+
+use super::TaskContext;
+
+core::arch::global_asm!(include_str!("switch.S"));
+
+extern "C" {
+    /// Save the callee-saved registers of the current execution into
+    /// `current_task_cx_ptr`, then restore those of `next_task_cx_ptr` and
+    /// return on its stack.
+    pub fn __switch(current_task_cx_ptr: *mut TaskContext, next_task_cx_ptr: *const TaskContext);
+}
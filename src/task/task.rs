@@ -0,0 +1,35 @@
+//! Types related to task management
+
+use super::TaskContext;
+
+/// The task control block (TCB) of a task.
+#[derive(Copy, Clone)]
+pub struct TaskControlBlock {
+    /// The task status in its lifecycle
+    pub task_status: TaskStatus,
+    /// The task context, valid while the task is switched out
+    pub task_cx: TaskContext,
+    /// When the task first ran, in microseconds
+    pub start_time_us: Option<usize>,
+    /// CPU time used before the current run, in ticks of the `time` CSR
+    pub cpu_time: usize,
+    /// When the task was last switched in, in ticks of the `time` CSR
+    pub run_start: usize,
+    /// CPU time limit in milliseconds
+    pub time_limit_ms: usize,
+}
+
+/// The status of a task
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum TaskStatus {
+    /// uninitialized
+    UnInit,
+    /// ready to run
+    Ready,
+    /// running
+    Running,
+    /// sleeping until its deadline in the timer queue
+    Sleeping,
+    /// exited, or never loaded
+    Exited,
+}
@@ -0,0 +1,205 @@
+//! RISC-V timer-related functionality
+//!
+//! The `time` CSR counts ticks at the timebase frequency, which is read
+//! from the device tree at boot by `init`.
+//!
+//! Sleeping tasks wait in `TIMERS`, a min-heap of deadlines. The SBI timer is
+//! always programmed for the end of the time slice or the earliest deadline,
+//! whichever comes first.
+
+use crate::config::{env_usize, DEFAULT_CLOCK_FREQ, DEFAULT_TIME_SLICE_MS, MAX_APP_NUM};
+use crate::fdt::{prop_to_usize, Fdt};
+use crate::sbi::set_timer;
+use crate::sync::IrqSafeLock;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use lazy_static::*;
+use riscv::register::time;
+
+const MSEC_PER_SEC: usize = 1000;
+const MICRO_PER_SEC: usize = 1_000_000;
+const NANO_PER_SEC: usize = 1_000_000_000;
+
+/// Frequency of the `time` CSR in Hz
+static CLOCK_FREQ: AtomicUsize = AtomicUsize::new(DEFAULT_CLOCK_FREQ);
+
+/// Read `timebase-frequency` from the device tree at `dtb`.
+///
+/// It is a property of `/cpus`, or of each `/cpus/cpu@N` on some boards.
+/// If it cannot be found, `DEFAULT_CLOCK_FREQ` is kept.
+pub fn init(dtb: usize) {
+    let freq = unsafe { Fdt::from_addr(dtb) }
+        .and_then(|fdt| {
+            fdt.find_property(
+                |path| match path {
+                    [cpus] => *cpus == b"cpus",
+                    [cpus, cpu] => *cpus == b"cpus" && cpu.starts_with(b"cpu@"),
+                    _ => false,
+                },
+                "timebase-frequency",
+            )
+        })
+        .and_then(prop_to_usize)
+        .filter(|&freq| freq >= MICRO_PER_SEC);
+    match freq {
+        Some(freq) => {
+            CLOCK_FREQ.store(freq, Ordering::Relaxed);
+            info!("[kernel] timebase-frequency = {} Hz", freq);
+        }
+        None => warn!(
+            "[kernel] no timebase-frequency in the device tree, using {} Hz",
+            DEFAULT_CLOCK_FREQ
+        ),
+    }
+}
+
+/// Frequency of the `time` CSR in Hz
+pub fn clock_freq() -> usize {
+    CLOCK_FREQ.load(Ordering::Relaxed)
+}
+
+/// Read the `time` CSR, which counts ticks since boot
+pub fn get_time() -> usize {
+    time::read()
+}
+
+/// Convert ticks of the `time` CSR into microseconds
+pub fn ticks_to_us(ticks: usize) -> usize {
+    let freq = clock_freq();
+    // split to avoid overflowing `ticks * MICRO_PER_SEC`
+    ticks / freq * MICRO_PER_SEC + ticks % freq * MICRO_PER_SEC / freq
+}
+
+/// Convert `(seconds, nanoseconds)` into ticks of the `time` CSR
+pub fn sec_ns_to_ticks(sec: usize, nsec: usize) -> usize {
+    let freq = clock_freq();
+    sec.saturating_mul(freq)
+        .saturating_add(nsec * freq / NANO_PER_SEC)
+}
+
+/// Convert ticks of the `time` CSR into `(seconds, nanoseconds)`
+pub fn ticks_to_sec_ns(ticks: usize) -> (usize, usize) {
+    let freq = clock_freq();
+    (ticks / freq, ticks % freq * NANO_PER_SEC / freq)
+}
+
+/// Get the current time in microseconds
+pub fn get_time_us() -> usize {
+    ticks_to_us(get_time())
+}
+
+/// The time slice of a task in milliseconds.
+///
+/// Set at build time with the `TIME_SLICE_MS` environment variable.
+pub fn time_slice_ms() -> usize {
+    env_usize(option_env!("TIME_SLICE_MS"), DEFAULT_TIME_SLICE_MS)
+}
+
+/// Start a new time slice: set the next timer interrupt one time slice from now,
+/// or at the earliest deadline of a sleeping task if that comes first.
+///
+/// Returns the end of the slice.
+pub fn set_next_trigger() -> usize {
+    let slice_end = get_time() + clock_freq() / MSEC_PER_SEC * time_slice_ms();
+    set_trigger(slice_end);
+    slice_end
+}
+
+/// Set the next timer interrupt at `slice_end`, the end of the current time slice,
+/// or at the earliest deadline of a sleeping task if that comes first
+pub fn set_trigger(slice_end: usize) {
+    set_timer(next_deadline().map_or(slice_end, |deadline| deadline.min(slice_end)));
+}
+
+/// A sleeping task and when to wake it up
+#[derive(Clone, Copy)]
+struct TimerEntry {
+    /// in ticks of the `time` CSR
+    deadline: usize,
+    task_id: usize,
+}
+
+/// Min-heap of `TimerEntry` ordered by deadline.
+///
+/// A task sleeps at most once at a time, so `MAX_APP_NUM` entries are enough.
+struct TimerQueue {
+    heap: [TimerEntry; MAX_APP_NUM],
+    len: usize,
+}
+
+impl TimerQueue {
+    fn push(&mut self, entry: TimerEntry) {
+        assert!(self.len < MAX_APP_NUM, "timer queue is full");
+        let mut i = self.len;
+        self.heap[i] = entry;
+        self.len += 1;
+        // sift up
+        while i > 0 {
+            let parent = (i - 1) / 2;
+            if self.heap[parent].deadline <= self.heap[i].deadline {
+                break;
+            }
+            self.heap.swap(parent, i);
+            i = parent;
+        }
+    }
+
+    fn peek(&self) -> Option<TimerEntry> {
+        if self.len == 0 {
+            None
+        } else {
+            Some(self.heap[0])
+        }
+    }
+
+    fn pop(&mut self) -> Option<TimerEntry> {
+        let top = self.peek()?;
+        self.len -= 1;
+        self.heap[0] = self.heap[self.len];
+        // sift down
+        let mut i = 0;
+        loop {
+            let mut smallest = i;
+            for child in [2 * i + 1, 2 * i + 2] {
+                if child < self.len && self.heap[child].deadline < self.heap[smallest].deadline {
+                    smallest = child;
+                }
+            }
+            if smallest == i {
+                break;
+            }
+            self.heap.swap(i, smallest);
+            i = smallest;
+        }
+        Some(top)
+    }
+}
+
+lazy_static! {
+    /// The deadlines of all sleeping tasks, read by the timer interrupt handler
+    static ref TIMERS: IrqSafeLock<TimerQueue> = IrqSafeLock::new(TimerQueue {
+        heap: [TimerEntry {
+            deadline: 0,
+            task_id: 0,
+        }; MAX_APP_NUM],
+        len: 0,
+    });
+}
+
+/// Wake up task `task_id` once the `time` CSR reaches `deadline`
+pub fn add_timer(deadline: usize, task_id: usize) {
+    TIMERS.lock().push(TimerEntry { deadline, task_id });
+}
+
+/// The earliest deadline of a sleeping task
+pub fn next_deadline() -> Option<usize> {
+    TIMERS.lock().peek().map(|entry| entry.deadline)
+}
+
+/// Remove and return a task whose deadline has passed, if any
+pub fn pop_expired_timer() -> Option<usize> {
+    let mut timers = TIMERS.lock();
+    match timers.peek() {
+        Some(entry) if entry.deadline <= get_time() => timers.pop().map(|entry| entry.task_id),
+        _ => None,
+    }
+}
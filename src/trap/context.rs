@@ -1,6 +1,14 @@
 //! Implement the public struct `TrapContext`
+use core::fmt;
 use riscv::register::sstatus::{self, Sstatus, SPP};
 
+/// ABI names of the general registers `x0`-`x31`
+pub const REG_NAMES: [&str; 32] = [
+    "zero", "ra", "sp", "gp", "tp", "t0", "t1", "t2", "s0", "s1", "a0", "a1", "a2", "a3", "a4",
+    "a5", "a6", "a7", "s2", "s3", "s4", "s5", "s6", "s7", "s8", "s9", "s10", "s11", "t3", "t4",
+    "t5", "t6",
+];
+
 #[repr(C)]
 /// Trap Context.
 /// 
@@ -14,6 +22,11 @@ pub struct TrapContext {
     pub sstatus: Sstatus,
     /// CSR sepc (Supervisor-mode Exception Program Counter): return address
     pub sepc: usize,
+    /// `tp` of the kernel, i.e., the id of the hart the app runs on.
+    /// Apps may use `tp` for themselves, so `__restore` keeps the kernel's
+    /// value here and `__alltraps` loads it back.
+    /// A trap from the kernel itself does not touch `tp`.
+    pub kernel_tp: usize,
 }
 
 impl TrapContext {
@@ -24,7 +37,7 @@ impl TrapContext {
     /// Init user application context
     /// 
     /// - Set the previous privilege mode as "user mode" in `sstatus`'s `SPP`.
-    /// - Set `sepc` as the entry point of the user application `0x80400000`.
+    /// - Set `sepc` as the entry point of the user application, i.e., `e_entry` of its ELF image.
     /// - Set the user stack pointer (at the stack base) in the `TrapContext`.
     pub fn app_init_context(entry: usize, sp: usize) -> Self {
         let mut sstatus = sstatus::read(); // CSR sstatus
@@ -33,8 +46,24 @@ impl TrapContext {
             x: [0; 32],
             sstatus,
             sepc: entry, // entry point of app
+            kernel_tp: 0, // set by `__restore`
         };
         cx.set_sp(sp); // app's user stack pointer
         cx // return initial Trap Context of app
     }
 }
+
+/// Register dump, four registers per line, by ABI name.
+impl fmt::Display for TrapContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, (name, value)) in REG_NAMES.iter().zip(self.x.iter()).enumerate() {
+            write!(f, "{:>4}: {:#018x}", name, value)?;
+            if i % 4 == 3 {
+                writeln!(f)?;
+            } else {
+                write!(f, "  ")?;
+            }
+        }
+        write!(f, "sepc: {:#018x}", self.sepc)
+    }
+}
@@ -0,0 +1,94 @@
+//! Crash report of an app killed by an exception
+//!
+//! Everything the kernel knows about the fault is printed before the
+//! `TrapContext` is thrown away: the registers, the trap CSRs, the words
+//! around the user stack pointer and the bytes of the faulting instruction.
+//! User memory is read through `mm`, so a wild `sp` or `sepc` only shows
+//! up as unreadable words and never faults the kernel.
+
+use super::TrapContext;
+use crate::mm::read_user;
+use crate::report::exception_names;
+use core::mem::size_of;
+use riscv::register::scause::{Exception, Scause};
+use riscv::register::sstatus::{Sstatus, SPP};
+
+/// Words dumped below the user stack pointer
+const STACK_WORDS_BELOW: usize = 4;
+/// Words dumped from the user stack pointer upwards
+const STACK_WORDS_ABOVE: usize = 12;
+/// Words per line of the stack dump
+const WORDS_PER_LINE: usize = 4;
+
+/// Print the crash report of the current app.
+pub fn print_crash_report(cx: &TrapContext, cause: Exception, scause: Scause, stval: usize) {
+    println!("[kernel] ---- crash report ----");
+    println!("{}", cx);
+    println!(
+        "sstatus: {:#018x}  (SPP = {}, SPIE = {})",
+        sstatus_bits(&cx.sstatus),
+        match cx.sstatus.spp() {
+            SPP::User => "U",
+            SPP::Supervisor => "S",
+        },
+        cx.sstatus.spie() as usize
+    );
+    println!(
+        " scause: {:#018x}  ({})",
+        scause.bits(),
+        exception_names(cause).0
+    );
+    println!("  stval: {:#018x}", stval);
+    print_instruction(cx.sepc);
+    print_stack(cx.x[2]);
+    println!("[kernel] ---- end of crash report ----");
+}
+
+/// Raw value of a saved `sstatus`.
+///
+/// `Sstatus` is a plain wrapper around the CSR bits, but the riscv crate
+/// does not expose them.
+fn sstatus_bits(sstatus: &Sstatus) -> usize {
+    unsafe { *(sstatus as *const Sstatus as *const usize) }
+}
+
+/// Bytes of the instruction at `sepc`: 2 for a compressed instruction, 4 otherwise.
+fn print_instruction(sepc: usize) {
+    print!("   inst: {:#018x}:", sepc);
+    let first = match read_user(sepc as *const u16) {
+        Some(half) => half,
+        None => {
+            println!(" <unreadable>");
+            return;
+        }
+    };
+    // the two lowest bits are 0b11 for a 32-bit instruction
+    let len = if first & 0b11 == 0b11 { 4 } else { 2 };
+    for i in 0..len {
+        match read_user((sepc + i) as *const u8) {
+            Some(byte) => print!(" {:02x}", byte),
+            None => print!(" ??"),
+        }
+    }
+    println!();
+}
+
+/// Hexdump of the words around the user stack pointer, `sp` marked with `>`.
+fn print_stack(sp: usize) {
+    const WORD: usize = size_of::<usize>();
+    println!("  stack: sp = {:#x}", sp);
+    let start = (sp & !(WORD - 1)).wrapping_sub(STACK_WORDS_BELOW * WORD);
+    for line in 0..(STACK_WORDS_BELOW + STACK_WORDS_ABOVE) / WORDS_PER_LINE {
+        let line_addr = start.wrapping_add(line * WORDS_PER_LINE * WORD);
+        print!("  {:#018x}:", line_addr);
+        for i in 0..WORDS_PER_LINE {
+            let addr = line_addr.wrapping_add(i * WORD);
+            let mark = if addr == sp & !(WORD - 1) { '>' } else { ' ' };
+            match read_user(addr as *const usize) {
+                Some(word) => print!("{}{:016x}", mark, word),
+                None => print!("{}????????????????", mark),
+            }
+        }
+        println!();
+    }
+}
@@ -3,27 +3,54 @@
 //! `trap.S` has the assembly code for 
 //! **context saving** (denoted as function by the symbol `__alltraps`) 
 //! and **context recovery** (denoted as function by the symbol `__restore`).
+//!
+//! Traps taken in the kernel itself go to `__kerneltrap` instead, which saves
+//! the context on the current kernel stack: `stvec` points at it from
+//! `init` and `__alltraps` on, and back at `__alltraps` from `__restore` on.
 
 mod context;
+mod crash;
 
-use crate::batch::run_next_app;
+use crate::gdb;
+use crate::ksym::Symbolized;
+use crate::report::{exception_names, Outcome};
+use crate::smp::lock_kernel;
+use crash::print_crash_report;
 use crate::syscall::syscall;
+use crate::task::{
+    current_slice_end, current_time_limit_exceeded, exclude_current_cpu_time,
+    exit_current_and_run_next, suspend_current_and_run_next, wake_expired_tasks,
+};
+use crate::timer::{get_time, set_trigger};
 
 use riscv::register::{
     mtvec::TrapMode,
-    scause::{self, Exception, Trap},
+    scause::{self, Exception, Interrupt, Trap},
+    sie,
     stval, stvec,
 };
 
 core::arch::global_asm!(include_str!("trap.S"));
 
-/// initialize CSR `stvec` as  trap handler entry point, i.e., `__alltraps`.
+/// initialize CSR `stvec` as the trap handler entry point of the kernel, i.e., `__kerneltrap`.
+///
+/// `__restore` switches it to `__alltraps` when an app is run.
 pub fn init() {
     extern "C" {
-        fn __alltraps();
+        fn __kerneltrap();
+    }
+    unsafe {
+        stvec::write(__kerneltrap as usize, TrapMode::Direct);
     }
+}
+
+/// enable timer interrupt in `sie` CSR
+///
+/// Timer interrupts are taken while an app runs in user mode. The kernel itself
+/// runs with `sstatus.SIE` cleared, so it is never interrupted.
+pub fn enable_timer_interrupt() {
     unsafe {
-        stvec::write(__alltraps as usize, TrapMode::Direct);
+        sie::set_stimer();
     }
 }
 
@@ -43,28 +70,76 @@ pub fn init() {
 pub fn trap_handler(cx: &mut TrapContext) -> &mut TrapContext {
     let scause = scause::read(); // get trap cause
     let stval = stval::read(); // get extra value
+    let trapped_at = get_time();
+    // released in `__restore`
+    lock_kernel();
+    // the time spent waiting for another hart is not the app's CPU time
+    exclude_current_cpu_time(get_time() - trapped_at);
     match scause.cause() {
         Trap::Exception(Exception::UserEnvCall) => {
+            let ecall_pc = cx.sepc;
             cx.sepc += 4;
-            cx.x[10] = syscall(cx.x[17], [cx.x[10], cx.x[11], cx.x[12]]) as usize;
+            cx.x[10] = syscall(cx.x[17], [cx.x[10], cx.x[11], cx.x[12]], ecall_pc) as usize;
         }
-        Trap::Exception(Exception::StoreFault) | Trap::Exception(Exception::StorePageFault) => {
-            println!("[kernel] PageFault in application, kernel killed it.");
-            run_next_app();
+        Trap::Interrupt(Interrupt::SupervisorTimer) => {
+            // the time slice of the current app is used up,
+            // or a sleeping app is due
+            wake_expired_tasks();
+            if let Some(limit_ms) = current_time_limit_exceeded() {
+                println!("[kernel] Time limit exceeded in application, kernel killed it.");
+                exit_current_and_run_next(Outcome::TimeLimitExceeded { limit_ms });
+            }
+            let slice_end = current_slice_end();
+            if get_time() >= slice_end {
+                suspend_current_and_run_next();
+            } else {
+                // only a sleeper woke up: the current app keeps the rest of its slice,
+                // and an idle hart, if any, picks up the sleeper
+                set_trigger(slice_end);
+            }
         }
-        Trap::Exception(Exception::IllegalInstruction) => {
-            println!("[kernel] IllegalInstruction in application, kernel killed it.");
-            run_next_app();
+        Trap::Exception(Exception::Breakpoint) if gdb::enabled() => {
+            gdb::handle_breakpoint(cx);
         }
-        _ => {
-            panic!(
-                "Unsupported trap {:?}, stval = {:#x}!",
-                scause.cause(),
+        Trap::Exception(e) => {
+            // any other exception is the app's fault: kill it, and only it
+            println!(
+                "[kernel] {} in application at sepc = {:#x}, stval = {:#x}, kernel killed it.",
+                exception_names(e).0,
+                cx.sepc,
                 stval
             );
+            print_crash_report(cx, e, scause, stval);
+            exit_current_and_run_next(Outcome::Killed {
+                cause: e,
+                sepc: cx.sepc,
+                stval,
+            });
+        }
+        Trap::Interrupt(i) => {
+            // not enabled in `sie`, so it should not happen; let the app go on
+            warn!("[kernel] Unexpected interrupt {:?} in application, ignored", i);
         }
     }
     cx
 }
 
+#[no_mangle]
+/// handle a trap taken in S-mode, called by `__kerneltrap`
+///
+/// It means a kernel bug, since the kernel runs with interrupts disabled.
+/// Print what we know and panic.
+pub extern "C" fn trap_from_kernel(cx: &TrapContext) -> ! {
+    let scause = scause::read();
+    let stval = stval::read();
+    println!("[kernel] Trap from kernel");
+    println!("{}", cx);
+    panic!(
+        "Unsupported trap {:?} from kernel, stval = {:#x}, sepc = {}!",
+        scause.cause(),
+        stval,
+        Symbolized(cx.sepc)
+    );
+}
+
 pub use context::TrapContext;
@@ -3,12 +3,33 @@
 BOOTLOADER=rustsbi-qemu.bin
 KERNEL_BIN=target/riscv64gc-unknown-none-elf/release/os
 KERNEL_ENTRY_PA=0x80200000
+KERNEL_SYMBOLS=target/kernel.sym
 
+# link once, then embed the symbols of that link for backtraces, see `build.rs`
 cargo build --release
+rust-nm -n -C --defined-only "$KERNEL_BIN" > "$KERNEL_SYMBOLS"
+KERNEL_SYMBOLS="$KERNEL_SYMBOLS" cargo build --release
 rust-objcopy --binary-architecture=riscv64 "$KERNEL_BIN" --strip-all -O binary "$KERNEL_BIN".bin
+# with the GDB stub built in, the console is a TCP port for `target remote :1234`
+if [ "$GDB_STUB" = "ON" ]; then
+    CONSOLE="-display none -serial tcp::1234,server=on"
+else
+    CONSOLE="-nographic"
+fi
 qemu-system-riscv64 \
             -machine virt \
-            -nographic \
+            -smp "${SMP:-4}" \
+            $CONSOLE \
             -bios "$BOOTLOADER" \
             -device loader,file="$KERNEL_BIN".bin,addr="$KERNEL_ENTRY_PA"
-            
\ No newline at end of file
+STATUS=$?
+
+# exit status set by the kernel, see `qemu::ExitStatus`
+case $STATUS in
+    0) echo "[test] all apps passed" ;;
+    1) echo "[test] FAILED: an app exited with a non-zero code or was rejected" ;;
+    2) echo "[test] FAILED: an app was killed by the kernel" ;;
+    3) echo "[test] FAILED: kernel panic" ;;
+    *) echo "[test] FAILED: qemu exited with status $STATUS" ;;
+esac
+exit $STATUS